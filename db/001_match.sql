@@ -0,0 +1,21 @@
+-- SQLite
+-- ルームでの対戦結果
+CREATE TABLE IF NOT EXISTS match_result (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    room TEXT NOT NULL,
+    round INTEGER NOT NULL,
+    -- UNIX時間(秒)
+    played_at INTEGER NOT NULL
+);
+
+-- 対戦でのプレイヤー1人分の手札
+CREATE TABLE IF NOT EXISTS match_hand (
+    match_id INTEGER NOT NULL,
+    player TEXT NOT NULL,
+    -- カードのIDをカンマ区切りにしたもの
+    cards TEXT NOT NULL,
+    role TEXT NOT NULL,
+    score INTEGER NOT NULL,
+    winner INTEGER NOT NULL,
+    FOREIGN KEY (match_id) REFERENCES match_result(id)
+);
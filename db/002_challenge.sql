@@ -0,0 +1,15 @@
+-- SQLite
+-- デイリーチャレンジに提出されたデッキと結果
+CREATE TABLE IF NOT EXISTS challenge_entry (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    -- チャレンジの日付(YYYY-MM-DD)
+    date TEXT NOT NULL,
+    name TEXT NOT NULL,
+    -- カードのIDをカンマ区切りにしたもの
+    cards TEXT NOT NULL,
+    score INTEGER NOT NULL,
+    -- UNIX時間(秒)
+    submitted_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS challenge_entry_date_score ON challenge_entry (date, score);
@@ -0,0 +1,26 @@
+-- SQLite
+-- シミュレーションの実行結果
+CREATE TABLE IF NOT EXISTS run (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    -- 実行したAPI(postcardsなど)
+    kind TEXT NOT NULL,
+    -- カードのIDをカンマ区切りにしたもの
+    cards TEXT NOT NULL,
+    num INTEGER NOT NULL,
+    all_score INTEGER NOT NULL,
+    -- 役ごとの出現回数．列名は/postcardsのレスポンスのキーと同じ
+    nopair INTEGER NOT NULL,
+    onepair INTEGER NOT NULL,
+    twopair INTEGER NOT NULL,
+    threepair INTEGER NOT NULL,
+    strait INTEGER NOT NULL,
+    flush INTEGER NOT NULL,
+    fulhouse INTEGER NOT NULL,
+    fourpair INTEGER NOT NULL,
+    straitflush INTEGER NOT NULL,
+    royalflush INTEGER NOT NULL,
+    -- UNIX時間(秒)
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS run_created_at ON run (created_at);
@@ -0,0 +1,528 @@
+//! サーバーを立てずにシミュレーションするためのコマンドラインツールです．
+//! 結果を表，JSON，CSVで標準出力に書き出すので，シェルスクリプトから実験を回せます．
+//!
+//! 例: porker-cli --deck "As,Ks,Qs,Js,Ts,9h" --num 100000 --seed 1 --format csv
+//!     porker-cli --batch decks.csv --format json
+
+use rand::{rngs::StdRng, SeedableRng};
+use serde_json::json;
+
+use hack_ideatech::porker::{self, batch, distribution::Distribution, sampling, Card, Role, RoleCounts, ScoreSheet};
+
+const USAGE: &str = "\
+Usage: porker-cli [OPTIONS]
+
+Options:
+  -d, --deck <CARDS>       使うカード．IDか表記をカンマか空白で区切るか，JSONの配列で書きます (省略すると52枚すべて)
+  -f, --deck-file <PATH>   使うカードをファイルから読み込みます (書き方は--deckと同じ)
+  -n, --num <N>            配る手札の数 (既定は1000000)
+  -s, --seed <SEED>        乱数のシード (省略するとランダムに決めて出力します)
+  -m, --mode <MODE>        simulate, distribution, stratified, controlVariate のどれか (既定はsimulate)
+      --sheet <PATH>       スコアシートのJSONファイル
+      --batch-size <N>     distributionでのバッチ1つあたりの手札の数 (既定は100)
+  -b, --batch <PATH>       JSONLかCSVのファイル(-なら標準入力)のデッキをまとめて実行します
+                           結果は--format jsonならJSONL，それ以外はCSVで出力します
+  -o, --format <FORMAT>    table, json, csv のどれか (既定はtable)
+  -h, --help               このヘルプを表示します
+
+simulateは回数の上限がありません．それ以外は/postcardsと同じく100万回までです．";
+
+/// 実行方法です．
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Simulate,
+    Distribution,
+    Estimate(sampling::Strategy),
+}
+
+/// 出力の形式です．
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Table,
+    Json,
+    Csv,
+}
+
+/// コマンドライン引数です．
+struct Args {
+    deck: Vec<u32>,
+    num: u32,
+    seed: u64,
+    mode: Mode,
+    sheet: ScoreSheet,
+    batch_size: Option<u32>,
+    batch: Option<String>,
+    format: Format,
+}
+
+/// 実行結果です．
+enum Outcome {
+    Simulate {
+        role_count: RoleCounts,
+        sum_score: i64,
+        loop_num: u32,
+    },
+    Distribution {
+        role_count: RoleCounts,
+        sum_score: i64,
+        loop_num: u32,
+        distribution: Distribution,
+    },
+    Estimate(sampling::Estimate),
+}
+
+fn main() {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Some(path) = &args.batch {
+        match run_batch(path, &args) {
+            Ok(out) => print!("{}", out),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    match run(&args) {
+        Ok(outcome) => print!("{}", render(&args, &outcome)),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// 引数を読み込みます．--helpが指定された場合はNoneを返します．
+fn parse_args(mut iter: impl Iterator<Item = String>) -> anyhow::Result<Option<Args>> {
+    let mut deck = None;
+    let mut num = porker::MAX_LOOP_NUM;
+    let mut seed = None;
+    let mut mode = Mode::Simulate;
+    let mut sheet = ScoreSheet::default();
+    let mut batch_size = None;
+    let mut batch = None;
+    let mut format = Format::Table;
+
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .ok_or_else(|| anyhow::anyhow!("Error: {} needs a value", arg))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-d" | "--deck" => deck = Some(porker::parse_deck(&value()?)?),
+            "-f" | "--deck-file" => {
+                let path = value()?;
+                let text = std::fs::read_to_string(&path).map_err(|e| anyhow::anyhow!("{}: {}", path, e))?;
+                deck = Some(porker::parse_deck(&text)?);
+            }
+            "-n" | "--num" => num = parse_number(&arg, &value()?)?,
+            "-s" | "--seed" => seed = Some(parse_number(&arg, &value()?)?),
+            "-m" | "--mode" => {
+                mode = match value()?.as_str() {
+                    "simulate" => Mode::Simulate,
+                    "distribution" => Mode::Distribution,
+                    "stratified" => Mode::Estimate(sampling::Strategy::Stratified),
+                    "controlVariate" => Mode::Estimate(sampling::Strategy::ControlVariate),
+                    other => return Err(anyhow::anyhow!("Error: Unknown mode {:?}", other)),
+                }
+            }
+            "--sheet" => {
+                let path = value()?;
+                sheet = ScoreSheet::load(&path).map_err(|e| anyhow::anyhow!("{}: {}", path, e))?;
+            }
+            "--batch-size" => batch_size = Some(parse_number(&arg, &value()?)?),
+            "-b" | "--batch" => batch = Some(value()?),
+            "-o" | "--format" => {
+                format = match value()?.as_str() {
+                    "table" => Format::Table,
+                    "json" => Format::Json,
+                    "csv" => Format::Csv,
+                    other => return Err(anyhow::anyhow!("Error: Unknown format {:?}", other)),
+                }
+            }
+            other => return Err(anyhow::anyhow!("Error: Unknown option {:?}", other)),
+        }
+    }
+
+    Ok(Some(Args {
+        deck: deck.unwrap_or_else(Card::all_cards_id),
+        num,
+        seed: porker::resolve_seed(seed),
+        mode,
+        sheet,
+        batch_size,
+        batch,
+        format,
+    }))
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> anyhow::Result<T> {
+    value
+        .replace('_', "")
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Error: {} must be a number (got {:?})", name, value))
+}
+
+fn run(args: &Args) -> anyhow::Result<Outcome> {
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    match args.mode {
+        Mode::Simulate => {
+            let (role_count, sum_score, loop_num) = porker::run_porker_with_rng(
+                &args.deck,
+                args.num,
+                &args.sheet,
+                args.num,
+                |_, _, _| true,
+                |_, _, _| {},
+                &mut rng,
+            )?;
+            Ok(Outcome::Simulate {
+                role_count,
+                sum_score,
+                loop_num,
+            })
+        }
+        Mode::Distribution => {
+            let (role_count, sum_score, loop_num, distribution) = porker::distribution::distribution_porker(
+                &args.deck,
+                args.num,
+                &args.sheet,
+                args.batch_size,
+                &mut rng,
+            )?;
+            Ok(Outcome::Distribution {
+                role_count,
+                sum_score,
+                loop_num,
+                distribution,
+            })
+        }
+        Mode::Estimate(strategy) => Ok(Outcome::Estimate(sampling::estimate_porker(
+            &args.deck,
+            args.num,
+            strategy,
+            Some(args.seed),
+            &args.sheet,
+        )?)),
+    }
+}
+
+/// ファイルのデッキをまとめて実行し，結果を書き出します．行ごとの回数やシードはファイルの値を使います．
+fn run_batch(path: &str, args: &Args) -> anyhow::Result<String> {
+    let text = if path == "-" {
+        std::io::read_to_string(std::io::stdin())?
+    } else {
+        std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("{}: {}", path, e))?
+    };
+    let rows = batch::parse_batch(&text, batch::BatchFormat::detect(&text))?;
+    let workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let results = batch::run_batch(&rows, &args.sheet, &Default::default(), workers);
+    let format = match args.format {
+        Format::Json => batch::BatchFormat::Jsonl,
+        Format::Table | Format::Csv => batch::BatchFormat::Csv,
+    };
+    Ok(batch::write_batch(&results, format))
+}
+
+fn render(args: &Args, outcome: &Outcome) -> String {
+    match args.format {
+        Format::Table => render_table(args, outcome),
+        Format::Json => render_json(args, outcome) + "\n",
+        Format::Csv => render_csv(args, outcome),
+    }
+}
+
+/// 役ごとの回数と割合の表です．
+fn role_table(role_count: &RoleCounts) -> String {
+    let mut out = format!("{:<12} {:>10} {:>12}\n", "role", "count", "probability");
+    for (role, p) in role_count.probabilities() {
+        out += &format!("{:<12} {:>10} {:>11.6}%\n", role.name(), role_count[role], p * 100.);
+    }
+    out
+}
+
+fn render_table(args: &Args, outcome: &Outcome) -> String {
+    let deck: Vec<String> = args.deck.iter().map(|id| Card::new(*id).to_string()).collect();
+    let mut out = format!("deck: {} ({} cards)\nseed: {}\n", deck.join(" "), deck.len(), args.seed);
+
+    match outcome {
+        Outcome::Simulate {
+            role_count,
+            sum_score,
+            loop_num,
+        } => {
+            out += &format!("number: {}\n\n", loop_num);
+            out += &role_table(role_count);
+            out += &format!("\nallscore: {}\n", sum_score);
+        }
+        Outcome::Distribution {
+            role_count,
+            sum_score,
+            loop_num,
+            distribution,
+        } => {
+            out += &format!("number: {}\n\n", loop_num);
+            out += &role_table(role_count);
+            out += &format!(
+                "\nallscore: {}\nmean: {:.4}  variance: {:.4}\n",
+                sum_score, distribution.mean, distribution.variance
+            );
+            if let Some(p) = &distribution.percentiles {
+                out += &format!("percentiles: p5={} p50={} p95={}\n", p.p5, p.p50, p.p95);
+            }
+            out += &format!("\n{:>10} {:>10}\n", "score", "count");
+            for bin in distribution.histogram.iter() {
+                out += &format!("{:>10} {:>10}\n", bin.score, bin.count);
+            }
+            let batches = &distribution.batches;
+            out += &format!(
+                "\nbatches: {} x {} hands  mean: {:.4}  variance: {:.4}\n",
+                batches.count, batches.size, batches.mean, batches.variance
+            );
+            if let Some(p) = &batches.percentiles {
+                out += &format!("percentiles: p5={} p50={} p95={}\n", p.p5, p.p50, p.p95);
+            }
+        }
+        Outcome::Estimate(estimate) => {
+            out += &format!("number: {}\n\n", estimate.loop_num);
+            out += &format!("{:<12} {:>14}\n", "role", "probability");
+            for (role, p) in estimate.probabilities.iter() {
+                out += &format!("{:<12} {:>13.8}%\n", role.name(), p * 100.);
+            }
+            out += &format!(
+                "\nmeanScore: {:.6}  stdError: {:.6}\neffectiveSampleSize: {:.0}\n",
+                estimate.mean_score, estimate.std_error, estimate.effective_sample_size
+            );
+        }
+    }
+    out
+}
+
+/// /postcardsや/estimateのレスポンスと同じ名前のJSONにします．
+fn render_json(args: &Args, outcome: &Outcome) -> String {
+    let value = match outcome {
+        Outcome::Simulate {
+            role_count,
+            sum_score,
+            loop_num,
+        } => json!({
+            "allscore": sum_score,
+            "number": loop_num,
+            "seed": args.seed,
+            "result": role_count,
+        }),
+        Outcome::Distribution {
+            role_count,
+            sum_score,
+            loop_num,
+            distribution,
+        } => json!({
+            "allscore": sum_score,
+            "number": loop_num,
+            "seed": args.seed,
+            "result": role_count,
+            "distribution": distribution,
+        }),
+        Outcome::Estimate(estimate) => json!({
+            "strategy": estimate.strategy,
+            "number": estimate.loop_num,
+            "seed": estimate.seed,
+            "meanScore": estimate.mean_score,
+            "stdError": estimate.std_error,
+            "effectiveSampleSize": estimate.effective_sample_size,
+            "probabilities": estimate.probabilities,
+        }),
+    };
+    value.to_string()
+}
+
+/// 見出しの行と，値の行を出力します．distributionはヒストグラムを出力します．
+fn render_csv(args: &Args, outcome: &Outcome) -> String {
+    let roles = || Role::ALL.iter().map(|role| role.name().to_string());
+    match outcome {
+        Outcome::Simulate {
+            role_count,
+            sum_score,
+            loop_num,
+        } => {
+            let header: Vec<String> = ["number", "allscore", "seed"].map(String::from).into_iter().chain(roles()).collect();
+            let row: Vec<String> = [loop_num.to_string(), sum_score.to_string(), args.seed.to_string()]
+                .into_iter()
+                .chain(Role::ALL.iter().map(|role| role_count[*role].to_string()))
+                .collect();
+            format!("{}\n{}\n", header.join(","), row.join(","))
+        }
+        Outcome::Distribution { distribution, .. } => {
+            let mut out = "score,count\n".to_string();
+            for bin in distribution.histogram.iter() {
+                out += &format!("{},{}\n", bin.score, bin.count);
+            }
+            out
+        }
+        Outcome::Estimate(estimate) => {
+            let header: Vec<String> = ["strategy", "number", "seed", "meanScore", "stdError", "effectiveSampleSize"]
+                .map(String::from)
+                .into_iter()
+                .chain(roles())
+                .collect();
+            let row: Vec<String> = [
+                json!(estimate.strategy).as_str().unwrap_or_default().to_string(),
+                estimate.loop_num.to_string(),
+                estimate.seed.to_string(),
+                estimate.mean_score.to_string(),
+                estimate.std_error.to_string(),
+                estimate.effective_sample_size.to_string(),
+            ]
+            .into_iter()
+            .chain(Role::ALL.iter().map(|role| estimate.probabilities.get(role).copied().unwrap_or(0.).to_string()))
+            .collect();
+            format!("{}\n{}\n", header.join(","), row.join(","))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> anyhow::Result<Option<Args>> {
+        parse_args(args.iter().map(|x| x.to_string()))
+    }
+
+    /// 手札10回分の決まった結果です．
+    fn simulate_outcome() -> Outcome {
+        let mut role_count = RoleCounts::new();
+        role_count[Role::NoPair] = 6;
+        role_count[Role::OnePair] = 3;
+        role_count[Role::Flush] = 1;
+        Outcome::Simulate {
+            role_count,
+            sum_score: 35,
+            loop_num: 10,
+        }
+    }
+
+    #[test]
+    fn parse_args_reads_flags() {
+        let args = parse(&[
+            "--deck", "As,Ks 12 Ts", "-n", "1_000", "-s", "7", "-m", "stratified", "--batch-size", "50", "-o", "csv",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(args.deck, vec![0, 12, 12, 9]);
+        assert_eq!(args.num, 1000);
+        assert_eq!(args.seed, 7);
+        assert_eq!(args.mode, Mode::Estimate(sampling::Strategy::Stratified));
+        assert_eq!(args.batch_size, Some(50));
+        assert_eq!(args.format, Format::Csv);
+
+        let args = parse(&["-d", r#"[0, "Kd"]"#, "-b", "-", "-m", "distribution", "-o", "json"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(args.deck, vec![0, 38]);
+        assert_eq!(args.batch.as_deref(), Some("-"));
+        assert_eq!(args.mode, Mode::Distribution);
+        assert_eq!(args.format, Format::Json);
+
+        // 省略すると52枚すべてを上限の回数だけ，表で出力する
+        let args = parse(&[]).unwrap().unwrap();
+        assert_eq!(args.deck, Card::all_cards_id());
+        assert_eq!(args.num, porker::MAX_LOOP_NUM);
+        assert_eq!(args.mode, Mode::Simulate);
+        assert_eq!(args.format, Format::Table);
+        assert!(args.batch.is_none());
+
+        assert!(parse(&["-n", "10", "--help"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn parse_args_rejects_bad_input() {
+        for (args, message) in [
+            (&["--nope"][..], "Error: Unknown option \"--nope\""),
+            (&["-m", "fast"][..], "Error: Unknown mode \"fast\""),
+            (&["-o", "xml"][..], "Error: Unknown format \"xml\""),
+            (&["--num"][..], "Error: --num needs a value"),
+            (&["-n", "many"][..], "Error: -n must be a number (got \"many\")"),
+            (&["-s", "-1"][..], "Error: -s must be a number (got \"-1\")"),
+            (&["--batch-size", "1.5"][..], "Error: --batch-size must be a number (got \"1.5\")"),
+        ] {
+            let error = parse(args).err().unwrap();
+            assert_eq!(error.to_string(), message);
+        }
+
+        // 読めないカードとIDの範囲外
+        assert!(parse(&["-d", "As,Zz"]).is_err());
+        assert!(parse(&["-d", "0,52"]).is_err());
+    }
+
+    #[test]
+    fn render_csv_and_json() {
+        let args = parse(&["-s", "7"]).unwrap().unwrap();
+        let outcome = simulate_outcome();
+
+        let csv = render_csv(&args, &outcome);
+        let roles: Vec<&str> = Role::ALL.iter().map(|role| role.name()).collect();
+        assert_eq!(
+            csv,
+            format!("number,allscore,seed,{}\n10,35,7,6,3,0,0,0,1,0,0,0,0\n", roles.join(","))
+        );
+
+        let value: serde_json::Value = serde_json::from_str(&render_json(&args, &outcome)).unwrap();
+        assert_eq!(value["allscore"], 35);
+        assert_eq!(value["number"], 10);
+        assert_eq!(value["seed"], 7);
+        assert_eq!(value["result"]["nopair"], 6);
+        assert_eq!(value["result"]["flush"], 1);
+    }
+
+    #[test]
+    fn render_estimate_and_distribution() {
+        let args = parse(&["-s", "7"]).unwrap().unwrap();
+        let outcome = Outcome::Estimate(sampling::Estimate {
+            strategy: sampling::Strategy::ControlVariate,
+            loop_num: 100,
+            seed: 7,
+            mean_score: 1.5,
+            std_error: 0.25,
+            effective_sample_size: 120.,
+            probabilities: Role::ALL.iter().map(|role| (*role, 0.1)).collect(),
+        });
+
+        let csv = render_csv(&args, &outcome);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert!(lines[0].starts_with("strategy,number,seed,meanScore,stdError,effectiveSampleSize,nopair,"));
+        assert!(lines[1].starts_with("controlVariate,100,7,1.5,0.25,120,0.1,"));
+        assert_eq!(lines[0].split(',').count(), lines[1].split(',').count());
+
+        let value: serde_json::Value = serde_json::from_str(&render_json(&args, &outcome)).unwrap();
+        assert_eq!(value["strategy"], "controlVariate");
+        assert_eq!(value["meanScore"], 1.5);
+        assert_eq!(value["probabilities"]["flush"], 0.1);
+
+        // distributionのCSVはヒストグラムで，回数の合計は配った手札の数になる
+        let args = parse(&["-n", "1000", "-s", "7", "-m", "distribution"]).unwrap().unwrap();
+        let outcome = run(&args).unwrap();
+        let csv = render_csv(&args, &outcome);
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("score,count"));
+        let total: u32 = lines.map(|x| x.split(',').nth(1).unwrap().parse::<u32>().unwrap()).sum();
+        assert_eq!(total, 1000);
+
+        let value: serde_json::Value = serde_json::from_str(&render_json(&args, &outcome)).unwrap();
+        assert_eq!(value["number"], 1000);
+        assert!(value["distribution"]["histogram"].is_array());
+    }
+}
@@ -0,0 +1,170 @@
+//! サーバーの設定を読み込むモジュールです．
+//! TOMLの設定ファイルを読み込み，環境変数が指定されていればその値で上書きします．
+//! 設定ファイルは環境変数PORKER_CONFIGで指定し，指定が無ければporker.tomlがあれば読み込みます．
+
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::jobs::{JOB_MAX_LOOP_NUM, JOB_MAX_PENDING};
+use crate::porker::Limits;
+
+/// 設定ファイルのパスを指定する環境変数です．
+const CONFIG_ENV: &str = "PORKER_CONFIG";
+
+/// 環境変数で指定しなかったときに読み込む設定ファイルです．無ければ初期値を使います．
+const DEFAULT_CONFIG_PATH: &str = "porker.toml";
+
+/// リクエストの本文の大きさの上限の初期値です．
+const DEFAULT_MAX_PAYLOAD_BYTES: usize = 1 << 20;
+
+/// サーバーの設定です．指定しなかった項目は初期値になります．
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Config {
+    /// 待ち受けるアドレスとポート
+    pub host: String,
+    pub port: u16,
+    /// HTTPのワーカー数．省略するとCPUのスレッド数
+    pub workers: Option<usize>,
+    /// ジョブとバッチを並列に実行する数．省略するとCPUのスレッド数
+    pub job_workers: Option<usize>,
+    /// ジョブ1つのループ回数の上限
+    pub job_max_loop_num: u32,
+    /// 実行待ちと実行中のジョブの数の上限
+    pub job_max_pending: usize,
+    /// ログの出力レベル．環境変数RUST_LOGがあればそちらが優先されます
+    pub log_level: String,
+    /// 結果を保存するデータベース
+    pub database_url: String,
+    /// スコアシートとデッキの制約のJSONファイル
+    pub score_sheet: Option<String>,
+    pub deck_constraints: Option<String>,
+    /// ブラウザからのアクセスを許可するオリジン．"*"ならすべてのオリジンを許可します
+    pub cors_origins: Vec<String>,
+    /// リクエストの本文の大きさの上限(バイト)
+    pub max_payload_bytes: usize,
+    /// 実行回数などの上限
+    pub limits: Limits,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            host: "127.0.0.1".to_string(),
+            port: 5001,
+            workers: None,
+            job_workers: None,
+            job_max_loop_num: JOB_MAX_LOOP_NUM,
+            job_max_pending: JOB_MAX_PENDING,
+            log_level: "info".to_string(),
+            database_url: "sqlite:porker.db".to_string(),
+            score_sheet: None,
+            deck_constraints: None,
+            cors_origins: Vec::new(),
+            max_payload_bytes: DEFAULT_MAX_PAYLOAD_BYTES,
+            limits: Limits::default(),
+        }
+    }
+}
+
+/// 環境変数の値を読み込みます．指定されていなければNone，読み込めなければエラーです．
+fn env_value<T: FromStr>(env: &impl Fn(&str) -> Option<String>, name: &str) -> anyhow::Result<Option<T>> {
+    env(name)
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("Error: {} has an invalid value {:?}", name, value))
+        })
+        .transpose()
+}
+
+/// 環境変数が指定されていれば，その値で設定を上書きします．
+fn override_with<T: FromStr>(env: &impl Fn(&str) -> Option<String>, name: &str, target: &mut T) -> anyhow::Result<()> {
+    if let Some(value) = env_value(env, name)? {
+        *target = value;
+    }
+    Ok(())
+}
+
+/// 省略できる項目をoverride_withと同じように上書きします．
+fn override_option<T: FromStr>(
+    env: &impl Fn(&str) -> Option<String>,
+    name: &str,
+    target: &mut Option<T>,
+) -> anyhow::Result<()> {
+    if let Some(value) = env_value(env, name)? {
+        *target = Some(value);
+    }
+    Ok(())
+}
+
+impl Config {
+    /// 設定ファイルと環境変数から設定を読み込みます．
+    pub fn load() -> anyhow::Result<Config> {
+        let mut config = match std::env::var(CONFIG_ENV) {
+            Ok(path) => Config::from_file(&path)?,
+            Err(_) if Path::new(DEFAULT_CONFIG_PATH).exists() => Config::from_file(DEFAULT_CONFIG_PATH)?,
+            Err(_) => Config::default(),
+        };
+        config.apply_env(|name| std::env::var(name).ok())?;
+        config.resolve();
+        Ok(config)
+    }
+
+    /// TOMLの設定ファイルを読み込みます．
+    pub fn from_file(path: &str) -> anyhow::Result<Config> {
+        let text = std::fs::read_to_string(path).map_err(|e| anyhow!("Error: {}: {}", path, e))?;
+        toml::from_str(&text).map_err(|e| anyhow!("Error: {}: {}", path, e))
+    }
+
+    /// 環境変数の値で上書きします．envは環境変数の名前から値を返す関数です．
+    /// CORSのオリジンはカンマ区切りで指定します．
+    pub fn apply_env(&mut self, env: impl Fn(&str) -> Option<String>) -> anyhow::Result<()> {
+        override_with(&env, "PORKER_HOST", &mut self.host)?;
+        override_with(&env, "PORKER_PORT", &mut self.port)?;
+        override_with(&env, "PORKER_LOG_LEVEL", &mut self.log_level)?;
+        override_with(&env, "PORKER_JOB_MAX_LOOP_NUM", &mut self.job_max_loop_num)?;
+        override_with(&env, "PORKER_JOB_MAX_PENDING", &mut self.job_max_pending)?;
+        override_with(&env, "PORKER_MAX_LOOP_NUM", &mut self.limits.max_loop_num)?;
+        override_with(&env, "PORKER_MAX_TRACE_LOOP_NUM", &mut self.limits.max_trace_loop_num)?;
+        override_with(&env, "PORKER_MAX_EVALUATE_HANDS", &mut self.limits.max_evaluate_hands)?;
+        override_with(&env, "PORKER_MAX_BATCH_ROWS", &mut self.limits.max_batch_rows)?;
+        override_with(&env, "PORKER_MAX_USE_CARDS", &mut self.limits.max_use_cards)?;
+        override_with(&env, "PORKER_MAX_PAYLOAD_BYTES", &mut self.max_payload_bytes)?;
+        override_with(&env, "DATABASE_URL", &mut self.database_url)?;
+
+        override_option(&env, "PORKER_WORKERS", &mut self.workers)?;
+        override_option(&env, "PORKER_JOB_WORKERS", &mut self.job_workers)?;
+        override_option(&env, "SCORE_SHEET", &mut self.score_sheet)?;
+        override_option(&env, "DECK_CONSTRAINTS", &mut self.deck_constraints)?;
+        if let Some(origins) = env("PORKER_CORS_ORIGINS") {
+            self.cors_origins = origins
+                .split(',')
+                .map(|x| x.trim().to_string())
+                .filter(|x| !x.is_empty())
+                .collect();
+        }
+        Ok(())
+    }
+
+    /// 省略されたワーカー数をCPUのスレッド数にします．
+    fn resolve(&mut self) {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        self.workers = Some(self.workers.unwrap_or(threads).max(1));
+        self.job_workers = Some(self.job_workers.unwrap_or(threads).max(1));
+    }
+
+    /// HTTPのワーカー数です．
+    pub fn workers(&self) -> usize {
+        self.workers.unwrap_or(1)
+    }
+
+    /// ジョブとバッチを並列に実行する数です．
+    pub fn job_workers(&self) -> usize {
+        self.job_workers.unwrap_or(1)
+    }
+}
@@ -1,11 +1,17 @@
+use std::future::Future;
 use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use futures_util::TryStreamExt;
 use sqlx::{
-    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
-    Connection, Row, Sqlite, SqlitePool, Transaction,
+    query::Query,
+    sqlite::{
+        SqliteArguments, SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteRow, SqliteSynchronous,
+    },
+    Row, Sqlite, SqlitePool, Transaction,
 };
 
-use crate::{Request, Response};
+use crate::porker::{Role, RoleCounts};
 
 type DbResult<T> = Result<T, Box<dyn std::error::Error>>;
 
@@ -32,19 +38,43 @@ pub async fn migrate_database(pool: &SqlitePool) -> DbResult<()> {
     Ok(())
 }
 
-// ポーカーの実行結果を保存
+/// シミュレーションの実行結果を保存し，保存した結果のIDを返します．kindは実行したAPIの名前です．
 pub async fn insert_millionpoker(
     pool: &SqlitePool,
-    request: &Request,
-    response: &Response,
-) -> DbResult<()> {
-    Ok(())
+    kind: &str,
+    use_cards: &[u32],
+    num: u32,
+    role_count: &RoleCounts,
+    all_score: i64,
+) -> DbResult<i64> {
+    let sql = format!(
+        "
+        INSERT INTO run
+        (kind, cards, num, all_score, {}, created_at)
+        VALUES
+        (?, ?, ?, ?, {}?)
+    ",
+        role_columns(),
+        "?, ".repeat(Role::COUNT)
+    );
+    let mut query = sqlx::query(&sql)
+        .bind(kind)
+        .bind(join_cards(use_cards))
+        .bind(num)
+        .bind(all_score);
+    for role in Role::ALL {
+        query = query.bind(role_count[role]);
+    }
+
+    let id = query.bind(unix_time()?).execute(pool).await?.last_insert_rowid();
+    Ok(id)
 }
 
+#[allow(dead_code)]
 async fn insert_use_cards(
     tx: &mut Transaction<'_, Sqlite>,
     id: u32,
-    cards: &Vec<u32>,
+    cards: &[u32],
     count: u32,
 ) -> DbResult<()> {
     sqlx::query(
@@ -66,3 +96,283 @@ async fn insert_use_cards(
 
     Ok(())
 }
+
+/// 今のUNIX時間(秒)を返します．
+fn unix_time() -> DbResult<i64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64)
+}
+
+/// 対戦でのプレイヤー1人分の手札と判定結果です．
+/// 手札のID，表記，役，スコア，一番強い手札だったかどうか(同じ強さなら全員)
+#[derive(serde::Serialize, Debug, Clone)]
+#[allow(non_snake_case)]
+pub struct PlayerHand {
+    pub playerId: u64,
+    pub name: String,
+    pub cards: [u32; 5],
+    pub notation: Vec<String>,
+    pub role: Role,
+    pub score: i64,
+    pub winner: bool,
+}
+
+/// カードのIDをカンマ区切りにします．
+fn join_cards(cards: &[u32]) -> String {
+    cards.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(",")
+}
+
+/// ルームでの対戦結果を保存し，保存した対戦のIDを返します．
+pub async fn insert_match(pool: &SqlitePool, room: &str, round: u32, hands: &[PlayerHand]) -> DbResult<i64> {
+    let played_at = unix_time()?;
+
+    let mut tx = pool.begin().await?;
+
+    let match_id = sqlx::query(
+        "
+        INSERT INTO match_result
+        (room, round, played_at)
+        VALUES
+        (?, ?, ?)
+    ",
+    )
+    .bind(room)
+    .bind(round)
+    .bind(played_at)
+    .execute(&mut tx)
+    .await?
+    .last_insert_rowid();
+
+    for hand in hands {
+        sqlx::query(
+            "
+            INSERT INTO match_hand
+            (match_id, player, cards, role, score, winner)
+            VALUES
+            (?, ?, ?, ?, ?, ?)
+        ",
+        )
+        .bind(match_id)
+        .bind(&hand.name)
+        .bind(join_cards(&hand.cards))
+        .bind(hand.role.name())
+        .bind(hand.score)
+        .bind(hand.winner)
+        .execute(&mut tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(match_id)
+}
+
+/// デイリーチャレンジのランキングの1行分です．名前ごとに一番良いスコアだけを数えます．
+#[derive(serde::Serialize)]
+#[allow(non_snake_case)]
+pub struct LeaderboardEntry {
+    pub rank: u32,
+    pub name: String,
+    pub score: i64,
+    pub submittedAt: i64,
+}
+
+/// デイリーチャレンジに提出された結果を保存します．
+pub async fn insert_challenge_entry(
+    pool: &SqlitePool,
+    date: &str,
+    name: &str,
+    cards: &[u32],
+    score: i64,
+) -> DbResult<()> {
+    sqlx::query(
+        "
+        INSERT INTO challenge_entry
+        (date, name, cards, score, submitted_at)
+        VALUES
+        (?, ?, ?, ?, ?)
+    ",
+    )
+    .bind(date)
+    .bind(name)
+    .bind(join_cards(cards))
+    .bind(score)
+    .bind(unix_time()?)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// その日のチャレンジのランキングを，スコアの高い順にlimit件返します．
+/// 提出時刻は一番良いスコアを出したときのもので，同じスコアの場合は先に提出した方が上になります．
+pub async fn challenge_leaderboard(pool: &SqlitePool, date: &str, limit: u32) -> DbResult<Vec<LeaderboardEntry>> {
+    let rows = sqlx::query(
+        "
+        SELECT name, MAX(score) AS best, submitted_at
+        FROM challenge_entry
+        WHERE date = ?
+        GROUP BY name
+        ORDER BY best DESC, submitted_at ASC
+        LIMIT ?
+    ",
+    )
+    .bind(date)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .iter()
+        .enumerate()
+        .map(|(i, row)| LeaderboardEntry {
+            rank: i as u32 + 1,
+            name: row.get("name"),
+            score: row.get("best"),
+            submittedAt: row.get("submitted_at"),
+        })
+        .collect())
+}
+
+/// その日のチャレンジで，scoreより良いスコアを出した人数に1を足した順位を返します．
+pub async fn challenge_rank(pool: &SqlitePool, date: &str, score: i64) -> DbResult<u32> {
+    let row = sqlx::query(
+        "
+        SELECT COUNT(*) AS better
+        FROM (SELECT MAX(score) AS best FROM challenge_entry WHERE date = ? GROUP BY name)
+        WHERE best > ?
+    ",
+    )
+    .bind(date)
+    .bind(score)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.get::<i64, _>("better") as u32 + 1)
+}
+
+/// 保存したシミュレーションの実行結果1つ分です．
+#[derive(serde::Serialize, Debug, Clone)]
+#[allow(non_snake_case)]
+pub struct RunRecord {
+    pub id: i64,
+    pub kind: String,
+    pub useCards: Vec<u32>,
+    pub number: u32,
+    pub allscore: i64,
+    pub result: RoleCounts,
+    pub createdAt: i64,
+}
+
+/// 保存した実行結果の絞り込みです．指定しなかった項目では絞り込みません．
+/// from，toはUNIX時間(秒)，minNumは回数の下限，cardはデッキに入っているカードのIDです．
+/// limitとoffsetで，新しい順に並べたうちの何件目から何件を返すかを指定します．
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+#[allow(non_snake_case)]
+pub struct RunFilter {
+    pub kind: Option<String>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub minNum: Option<u32>,
+    pub card: Option<u32>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+/// 絞り込みのSQLを作ります．?の順番はbind_run_filterと同じです．
+fn run_filter_sql(filter: &RunFilter) -> String {
+    let mut conditions = vec!["1 = 1"];
+    if filter.kind.is_some() {
+        conditions.push("kind = ?");
+    }
+    if filter.from.is_some() {
+        conditions.push("created_at >= ?");
+    }
+    if filter.to.is_some() {
+        conditions.push("created_at < ?");
+    }
+    if filter.minNum.is_some() {
+        conditions.push("num >= ?");
+    }
+    if filter.card.is_some() {
+        conditions.push("(',' || cards || ',') LIKE ?");
+    }
+
+    format!(
+        "SELECT id, kind, cards, num, all_score, {}, created_at FROM run WHERE {} ORDER BY id DESC LIMIT ? OFFSET ?",
+        role_columns(),
+        conditions.join(" AND ")
+    )
+}
+
+fn bind_run_filter<'q>(
+    mut query: Query<'q, Sqlite, SqliteArguments<'q>>,
+    filter: &'q RunFilter,
+) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+    if let Some(kind) = &filter.kind {
+        query = query.bind(kind);
+    }
+    if let Some(from) = filter.from {
+        query = query.bind(from);
+    }
+    if let Some(to) = filter.to {
+        query = query.bind(to);
+    }
+    if let Some(min_num) = filter.minNum {
+        query = query.bind(min_num);
+    }
+    if let Some(card) = filter.card {
+        query = query.bind(format!("%,{},%", card));
+    }
+    // LIMITに負の値を渡すと上限なしになる
+    query
+        .bind(filter.limit.map_or(-1, |x| x as i64))
+        .bind(filter.offset.unwrap_or(0))
+}
+
+fn run_record(row: &SqliteRow) -> RunRecord {
+    let mut result = RoleCounts::new();
+    for role in Role::ALL {
+        result[role] = row.get(role.name());
+    }
+    let cards: String = row.get("cards");
+
+    RunRecord {
+        id: row.get("id"),
+        kind: row.get("kind"),
+        useCards: cards.split(',').filter_map(|x| x.parse().ok()).collect(),
+        number: row.get("num"),
+        allscore: row.get("all_score"),
+        result,
+        createdAt: row.get("created_at"),
+    }
+}
+
+/// 役ごとの出現回数の列名をカンマ区切りにします．列名はRole::nameと同じで，Role::ALLと同じ順に並べます．
+fn role_columns() -> String {
+    Role::ALL.iter().map(|role| role.name()).collect::<Vec<_>>().join(", ")
+}
+
+/// 保存した実行結果を，新しい順に絞り込んで返します．
+pub async fn list_runs(pool: &SqlitePool, filter: &RunFilter) -> DbResult<Vec<RunRecord>> {
+    let sql = run_filter_sql(filter);
+    let rows = bind_run_filter(sqlx::query(&sql), filter).fetch_all(pool).await?;
+    Ok(rows.iter().map(run_record).collect())
+}
+
+/// 保存した実行結果を，新しい順に絞り込んで1件ずつon_rowに渡します．
+/// すべてを読み込んでから渡すのではなく，SQLiteから1件読むたびに渡します．
+/// on_rowがfalseを返した場合は，そこで読み込みをやめます．
+pub async fn for_each_run<F, Fut>(pool: &SqlitePool, filter: &RunFilter, mut on_row: F) -> DbResult<()>
+where
+    F: FnMut(RunRecord) -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let sql = run_filter_sql(filter);
+    let mut rows = bind_run_filter(sqlx::query(&sql), filter).fetch(pool);
+    while let Some(row) = rows.try_next().await? {
+        if !on_row(run_record(&row)).await {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,69 @@
+//! 保存したシミュレーションの実行結果をCSVかJSONLで書き出すモジュールです．
+//! SQLiteから1行読むたびにチャンネル経由でレスポンスのストリームに流すので，全件をメモリに読み込みません．
+
+use std::convert::Infallible;
+
+use actix_web::web::Bytes;
+use futures_util::stream::{self, Stream};
+use sqlx::SqlitePool;
+use tokio::sync::mpsc;
+
+use crate::database::{self, RunFilter, RunRecord};
+use crate::porker::batch::BatchFormat;
+use crate::porker::Role;
+
+/// CSVの見出しの行です．役ごとの回数はRole::ALLの順に並べます．
+fn csv_header() -> String {
+    let roles: Vec<&str> = Role::ALL.iter().map(|role| role.name()).collect();
+    format!("id,kind,createdAt,number,allscore,useCards,{}\n", roles.join(","))
+}
+
+/// 実行結果1つ分の行です．useCardsはカンマ区切りなので"で囲みます．
+fn row(record: &RunRecord, format: BatchFormat) -> Bytes {
+    let line = match format {
+        BatchFormat::Jsonl => serde_json::to_string(record).unwrap_or_default(),
+        BatchFormat::Csv => {
+            let cards: Vec<String> = record.useCards.iter().map(|x| x.to_string()).collect();
+            let mut cells = vec![
+                record.id.to_string(),
+                record.kind.clone(),
+                record.createdAt.to_string(),
+                record.number.to_string(),
+                record.allscore.to_string(),
+                format!("\"{}\"", cards.join(",")),
+            ];
+            cells.extend(Role::ALL.iter().map(|role| record.result[*role].to_string()));
+            cells.join(",")
+        }
+    };
+    Bytes::from(line + "\n")
+}
+
+/// 書き出しを始め，行を流すストリームを返します．CSVでは最初に見出しの行を送ります．
+/// クライアントが切断した場合は，次の行を送ろうとした時点で読み込みをやめます．
+pub fn start(
+    pool: SqlitePool,
+    filter: RunFilter,
+    format: BatchFormat,
+) -> impl Stream<Item = Result<Bytes, Infallible>> {
+    let (tx, rx) = mpsc::channel::<Bytes>(64);
+
+    actix_web::rt::spawn(async move {
+        if format == BatchFormat::Csv && tx.send(Bytes::from(csv_header())).await.is_err() {
+            return;
+        }
+        let result = database::for_each_run(&pool, &filter, |record| {
+            let tx = tx.clone();
+            async move { tx.send(row(&record, format)).await.is_ok() }
+        })
+        .await;
+        // ヘッダーは送ってしまっているので，途中で失敗した場合はログに残すだけにする
+        if let Err(e) = result {
+            log::warn!("failed to export runs: {}", e);
+        }
+    });
+
+    stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|bytes| (Ok(bytes), rx))
+    })
+}
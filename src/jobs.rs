@@ -0,0 +1,247 @@
+//! シミュレーションを非同期ジョブとして実行するモジュールです．
+//! POSTされたジョブはすぐにIDを返し，ワーカー数を制限したプールでバックグラウンド実行されます．
+//! 状態はメモリ上にだけ保持しているので，サーバーを再起動すると消えます．
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use tokio::sync::Semaphore;
+
+use crate::porker::{self, RoleCounts, ScoreSheet};
+use crate::Response;
+
+/// ジョブで許可するループ回数の上限の初期値です．/postcardsの100万回より大きな回数を実行できます．
+pub const JOB_MAX_LOOP_NUM: u32 = 100_000_000;
+
+/// 実行待ちと実行中のジョブの数の上限の初期値です．これを超えるジョブは受け付けません．
+pub const JOB_MAX_PENDING: usize = 100;
+
+/// 進捗を更新する間隔(回数)です．キャンセルもこの間隔で確認します．
+const PROGRESS_EVERY: u32 = 10_000;
+
+/// 保持しておく終了済みジョブの数です．これを超えると古いものから消します．
+const MAX_FINISHED_JOBS: usize = 1000;
+
+/// ジョブの状態です．
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    fn is_finished(self) -> bool {
+        matches!(self, JobStatus::Done | JobStatus::Failed | JobStatus::Cancelled)
+    }
+}
+
+/// ジョブの終了結果です．
+enum JobOutcome {
+    Done(RoleCounts, i64, u32),
+    Failed(String),
+}
+
+/// ジョブ1つ分の状態です．ワーカーとハンドラの間で共有します．
+struct Job {
+    num: u32,
+    use_cards: Vec<u32>,
+    sheet: ScoreSheet,
+    progress: AtomicU32,
+    cancel: AtomicBool,
+    state: Mutex<(JobStatus, Option<JobOutcome>)>,
+}
+
+impl Job {
+    fn status(&self) -> JobStatus {
+        self.state.lock().unwrap().0
+    }
+
+    fn set_status(&self, status: JobStatus) {
+        self.state.lock().unwrap().0 = status;
+    }
+
+    fn finish(&self, status: JobStatus, outcome: Option<JobOutcome>) {
+        *self.state.lock().unwrap() = (status, outcome);
+    }
+}
+
+/// GET /jobs/{id} などで返すジョブの状態です．
+#[derive(Serialize)]
+pub struct JobView {
+    id: u64,
+    status: JobStatus,
+    number: u32,
+    progress: u32,
+    percent: f64,
+    result: Option<Response>,
+    error: Option<String>,
+}
+
+/// ジョブの一覧と，ワーカープールを管理する構造体です．
+pub struct JobQueue {
+    jobs: Mutex<BTreeMap<u64, Arc<Job>>>,
+    next_id: AtomicU64,
+    workers: Arc<Semaphore>,
+    max_loop_num: u32,
+    max_pending: usize,
+}
+
+impl JobQueue {
+    /// 同時に実行するジョブの数，ジョブ1つのループ回数の上限と，実行待ちと実行中のジョブの数の上限を指定して生成します．
+    pub fn new(workers: usize, max_loop_num: u32, max_pending: usize) -> JobQueue {
+        JobQueue {
+            jobs: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(1),
+            workers: Arc::new(Semaphore::new(workers.max(1))),
+            max_loop_num,
+            max_pending,
+        }
+    }
+
+    /// ジョブを登録してバックグラウンドで実行を始め，ジョブの状態を返します．
+    /// 回数が上限を超えていたら，上限まで減らします．
+    /// 実行待ちと実行中のジョブがすでに上限まであれば，登録せずにNoneを返します．
+    pub fn submit(&self, use_cards: Vec<u32>, num: u32, sheet: ScoreSheet) -> Option<JobView> {
+        let job = Arc::new(Job {
+            num: num.min(self.max_loop_num),
+            use_cards,
+            sheet,
+            progress: AtomicU32::new(0),
+            cancel: AtomicBool::new(false),
+            state: Mutex::new((JobStatus::Queued, None)),
+        });
+
+        let id = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let pending = jobs.values().filter(|job| !job.status().is_finished()).count();
+            if pending >= self.max_pending {
+                return None;
+            }
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            jobs.insert(id, job.clone());
+            prune_finished(&mut jobs);
+            id
+        };
+
+        let workers = self.workers.clone();
+        let worker_job = job.clone();
+        tokio::spawn(async move {
+            // ワーカーが空くまで待つ
+            let _permit = match workers.acquire_owned().await {
+                Ok(permit) => permit,
+                Err(e) => {
+                    worker_job.finish(JobStatus::Failed, Some(JobOutcome::Failed(e.to_string())));
+                    return;
+                }
+            };
+            if worker_job.cancel.load(Ordering::Relaxed) {
+                return;
+            }
+            worker_job.set_status(JobStatus::Running);
+
+            let job = worker_job.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                porker::run_porker(&job.use_cards, job.num, &job.sheet, PROGRESS_EVERY, |_, _, done| {
+                    job.progress.store(done, Ordering::Relaxed);
+                    !job.cancel.load(Ordering::Relaxed)
+                })
+            })
+            .await;
+
+            match result {
+                Ok(Ok((role_count, sum_score, loop_num))) => {
+                    worker_job.progress.store(loop_num, Ordering::Relaxed);
+                    worker_job.finish(
+                        JobStatus::Done,
+                        Some(JobOutcome::Done(role_count, sum_score, loop_num)),
+                    );
+                }
+                Ok(Err(_)) if worker_job.cancel.load(Ordering::Relaxed) => {
+                    worker_job.finish(JobStatus::Cancelled, None);
+                }
+                Ok(Err(e)) => {
+                    worker_job.finish(JobStatus::Failed, Some(JobOutcome::Failed(e.to_string())));
+                }
+                Err(e) => {
+                    worker_job.finish(JobStatus::Failed, Some(JobOutcome::Failed(e.to_string())));
+                }
+            }
+        });
+
+        Some(view(id, &job))
+    }
+
+    /// ジョブの状態を返します．存在しない場合はNoneを返します．
+    pub fn get(&self, id: u64) -> Option<JobView> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.get(&id).map(|job| view(id, job))
+    }
+
+    /// 実行待ちか実行中のジョブをキャンセルします．
+    /// 終了済みのジョブの場合は，保持している結果を削除します．
+    pub fn cancel(&self, id: u64) -> Option<JobView> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get(&id)?.clone();
+
+        let status = job.status();
+        if status.is_finished() {
+            jobs.remove(&id);
+        } else {
+            job.cancel.store(true, Ordering::Relaxed);
+            // 実行待ちのジョブはワーカーが拾う前にキャンセル済みにしておく
+            if status == JobStatus::Queued {
+                job.finish(JobStatus::Cancelled, None);
+            }
+        }
+
+        Some(view(id, &job))
+    }
+}
+
+/// 終了済みのジョブがMAX_FINISHED_JOBSを超えていたら，IDの小さい(古い)ものから消します．
+fn prune_finished(jobs: &mut BTreeMap<u64, Arc<Job>>) {
+    let finished: Vec<u64> = jobs
+        .iter()
+        .filter(|(_, job)| job.status().is_finished())
+        .map(|(id, _)| *id)
+        .collect();
+
+    if finished.len() > MAX_FINISHED_JOBS {
+        for id in finished.iter().take(finished.len() - MAX_FINISHED_JOBS) {
+            jobs.remove(id);
+        }
+    }
+}
+
+fn view(id: u64, job: &Job) -> JobView {
+    let progress = job.progress.load(Ordering::Relaxed);
+    let state = job.state.lock().unwrap();
+
+    let (result, error) = match &state.1 {
+        Some(JobOutcome::Done(role_count, sum_score, loop_num)) => {
+            (Some(Response::new(*sum_score, *loop_num, *role_count)), None)
+        }
+        Some(JobOutcome::Failed(e)) => (None, Some(e.clone())),
+        None => (None, None),
+    };
+
+    JobView {
+        id,
+        status: state.0,
+        number: job.num,
+        progress,
+        percent: if job.num == 0 {
+            100.
+        } else {
+            progress as f64 / job.num as f64 * 100.
+        },
+        result,
+        error,
+    }
+}
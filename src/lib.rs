@@ -0,0 +1,5 @@
+//! ideaxtechで作ったポーカーのシミュレーションのライブラリです．
+//! サーバー(hack_ideatech)とコマンドラインのシミュレーター(porker-cli)の両方から使います．
+
+pub mod database;
+pub mod porker;
@@ -1,86 +1,1261 @@
 //! ideaxtechで作ったソースコードです．Cargo docコマンドを使ってみようということで，一応ドキュメントにしてみました．
 
-use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder};
+use actix_cors::Cors;
+use actix_web::error::{InternalError, JsonPayloadError, PathError, QueryPayloadError};
+use actix_web::http::{header, StatusCode};
+use actix_web::{delete, get, post, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
 
-//mod database;
-mod porker;
+use porker::constraints::{ConstraintError, DeckConstraints, Violation};
+use porker::distribution::{Distribution, Percentiles};
+use database::RunFilter;
+use porker::{Role, RoleCounts, ScoreSheet};
+
+use hack_ideatech::{database, porker};
+
+mod config;
+mod export;
+mod jobs;
+mod rooms;
+mod stream;
+#[cfg(test)]
+mod test;
+
+/// デイリーチャレンジのランキングで返す人数です．
+const LEADERBOARD_SIZE: u32 = 10;
+
+/// /runs で件数を指定しなかったときに返す件数と，指定できる件数の上限です．
+const DEFAULT_RUNS_LIMIT: u32 = 50;
+const MAX_RUNS_LIMIT: u32 = 500;
 
 /// POSTされたデータを受け取るための構造体です．
-/// 回数，使うカードのIDベクタ
+/// 回数，使うカードのIDベクタ，手札を1つずつ記録するかどうか(省略可，回数1000回まで)，
+/// レスポンスにカードの表記を含めるかどうか(省略可)，スコアの分布を返すかどうか(省略可)，
+/// 分布のバッチ1つあたりの手札の数(省略可，既定は100)，
+/// 期待スコアの目標の相対精度(省略可，指定した場合numは最大回数)，その信頼度(省略可，既定は0.95)，
+/// /analyzeでカードの入れ替えも分析するかどうか(省略可)
+/// useCardsにはIDの代わりに"As"のような表記の文字列も使えます．
 //jsonのリクエストのフィールド名と名前が一致するように
 //allowアトリビュートで名前がスネークケースでない警告を無視
 #[derive(Deserialize)]
 #[allow(non_snake_case)]
 pub struct Request {
     num: u32,
+    #[serde(deserialize_with = "deserialize_card_ids")]
     useCards: Vec<u32>,
+    #[serde(default)]
+    trace: bool,
+    #[serde(default)]
+    names: bool,
+    #[serde(default)]
+    distribution: bool,
+    batchSize: Option<u32>,
+    precision: Option<f64>,
+    confidence: Option<f64>,
+    #[serde(default)]
+    swap: bool,
 }
 
-/// それぞれの役が何回出たか保持する構造体です．
-/// Response構造体の一部分でもあります．
-#[derive(Serialize)]
+/// /compare にPOSTされたデータを受け取るための構造体です．
+/// 回数，比べるデッキ(使うカードのIDベクタ)の配列，シード(省略可)
+#[derive(Deserialize)]
+pub struct CompareRequest {
+    num: u32,
+    #[serde(deserialize_with = "deserialize_decks")]
+    decks: Vec<Vec<u32>>,
+    seed: Option<u64>,
+}
+
+/// /showdown にPOSTされたデータを受け取るための構造体です．
+/// 回数，2人のデッキ(使うカードのIDベクタ)の配列，シード(省略可)
+#[derive(Deserialize)]
+pub struct ShowdownRequest {
+    num: u32,
+    #[serde(deserialize_with = "deserialize_decks")]
+    decks: Vec<Vec<u32>>,
+    seed: Option<u64>,
+}
+
+/// /table にPOSTされたデータを受け取るための構造体です．
+/// 回数，使うカードのIDベクタ，人数(2~10)，シード(省略可)
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+pub struct TableRequest {
+    num: u32,
+    #[serde(deserialize_with = "deserialize_card_ids")]
+    useCards: Vec<u32>,
+    players: usize,
+    seed: Option<u64>,
+}
+
+/// /estimate にPOSTされたデータを受け取るための構造体です．
+/// 回数，使うカードのIDベクタ，推定の方法(plain, stratified, controlVariate)，シード(省略可)
+#[derive(Deserialize)]
 #[allow(non_snake_case)]
-struct ResultRole {
-    nopair: u32,
-    onepair: u32,
-    twopair: u32,
-    threepair: u32,
-    fourpair: u32,
-    fulhouse: u32,
-    flush: u32,
-    strait: u32,
-    straitflush: u32,
-    royalflush: u32,
+pub struct EstimateRequest {
+    num: u32,
+    #[serde(deserialize_with = "deserialize_card_ids")]
+    useCards: Vec<u32>,
+    strategy: porker::sampling::Strategy,
+    seed: Option<u64>,
+}
+
+/// /batch のクエリです．
+/// 入力の形式(jsonlかcsv，省略すると内容から判断)，出力の形式(省略すると入力と同じ)
+#[derive(Deserialize)]
+pub struct BatchQuery {
+    input: Option<porker::batch::BatchFormat>,
+    output: Option<porker::batch::BatchFormat>,
+}
+
+/// /runs/export のクエリです．出力の形式(jsonlかcsv，省略するとcsv)
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    format: Option<porker::batch::BatchFormat>,
+}
+
+/// /sessions にPOSTされたデータを受け取るための構造体です．
+/// 使うカードのIDベクタ，1セッションの手札の数，セッションの回数，手札1つごとの参加費(省略可)，
+/// 最初の持ち点(省略可)，シード(省略可)
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+pub struct SessionRequest {
+    #[serde(deserialize_with = "deserialize_card_ids")]
+    useCards: Vec<u32>,
+    hands: u32,
+    sessions: u32,
+    #[serde(default)]
+    ante: i64,
+    bankroll: Option<i64>,
+    seed: Option<u64>,
+}
+
+/// /challenge/{date}/submit にPOSTされたデータを受け取るための構造体です．
+/// 名前，使うカードのIDベクタ
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+pub struct ChallengeSubmitRequest {
+    name: String,
+    #[serde(deserialize_with = "deserialize_card_ids")]
+    useCards: Vec<u32>,
+}
+
+/// /evaluate にPOSTされたデータを受け取るための列挙型です．
+/// 手札1つ({"hand": [...]})か，手札の配列({"hands": [[...], ...]})のどちらかを受け取ります．
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum EvaluateRequest {
+    One {
+        #[serde(deserialize_with = "deserialize_card_ids")]
+        hand: Vec<u32>,
+    },
+    Many {
+        #[serde(deserialize_with = "deserialize_decks")]
+        hands: Vec<Vec<u32>>,
+    },
+}
+
+/// IDと表記が混ざったカードの配列を，IDのベクタとして読み込みます．
+/// 読み込める形はporker::Cardと同じです．
+pub fn deserialize_card_ids<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u32>, D::Error> {
+    let cards = Vec::<porker::Card>::deserialize(deserializer)?;
+    check_use_cards_len(cards.len())?;
+    Ok(cards.iter().map(|x| x.id).collect())
+}
+
+/// deserialize_card_idsのデッキ配列版です．
+pub fn deserialize_decks<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Vec<u32>>, D::Error> {
+    let decks = Vec::<Vec<porker::Card>>::deserialize(deserializer)?;
+    for deck in &decks {
+        check_use_cards_len(deck.len())?;
+    }
+    Ok(decks.iter().map(|deck| deck.iter().map(|x| x.id).collect()).collect())
+}
+
+/// デッキのカードの数が上限を超えていたら，読み込みのエラーにします．
+fn check_use_cards_len<E: serde::de::Error>(len: usize) -> Result<(), E> {
+    let max = porker::limits().max_use_cards;
+    if len > max {
+        return Err(E::custom(format!("useCards must have at most {} cards", max)));
+    }
+    Ok(())
+}
+
+/// エラーのときに返すJSONです．
+/// エラーメッセージと，デッキが制約を満たしていない場合は破っているルールの一覧(それ以外は空)
+#[derive(Serialize)]
+pub(crate) struct ErrorResponse<'a> {
+    error: String,
+    violations: &'a [Violation],
+}
+
+impl ErrorResponse<'_> {
+    /// エラーメッセージだけのJSONを作ります．先頭に"Error: "が無ければ付けます．
+    fn message(message: impl std::fmt::Display) -> ErrorResponse<'static> {
+        let message = message.to_string();
+        let error = if message.starts_with("Error: ") {
+            message
+        } else {
+            format!("Error: {}", message)
+        };
+        ErrorResponse { error, violations: &[] }
+    }
+
+    /// エラーからJSONを作ります．
+    /// デッキの制約を破っている場合は，破っているルールの一覧も入れます．
+    pub(crate) fn from_error(e: &anyhow::Error) -> ErrorResponse<'_> {
+        match e.downcast_ref::<ConstraintError>() {
+            Some(error) => ErrorResponse {
+                error: "Error: useCards breaks deck constraints".to_string(),
+                violations: &error.violations,
+            },
+            None => ErrorResponse::message(e),
+        }
+    }
+}
+
+/// エラーを400 Bad RequestのJSONにします．
+/// デッキの制約を破っている場合は，破っているルールの一覧も返します．
+fn bad_request(e: anyhow::Error) -> HttpResponse {
+    HttpResponse::BadRequest().json(ErrorResponse::from_error(&e))
+}
+
+/// シミュレーションをブロッキング用のスレッドで実行し，成功したらrespondでレスポンスにします．
+/// 回数が多くてもactixのワーカーを止めないためです．
+async fn simulate<T, F, R>(run: F, respond: R) -> HttpResponse
+where
+    T: Send + 'static,
+    F: FnOnce() -> anyhow::Result<T> + Send + 'static,
+    R: FnOnce(T) -> HttpResponse,
+{
+    match web::block(run).await {
+        Ok(Ok(result)) => respond(result),
+        Ok(Err(e)) => bad_request(e),
+        Err(e) => error_json(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+/// デッキがすべて手札を配れて，制約を満たしているか確かめます．
+fn validate_decks(constraints: &DeckConstraints, decks: &[Vec<u32>]) -> anyhow::Result<()> {
+    for deck in decks {
+        porker::check_deck(deck)?;
+        constraints.validate(deck)?;
+    }
+    Ok(())
+}
+
+/// エラーメッセージを，ErrorResponseのJSONにします．メッセージが"Error: "で始まっていなければ付けます．
+fn error_json(status: StatusCode, message: impl std::fmt::Display) -> HttpResponse {
+    HttpResponse::build(status).json(ErrorResponse::message(message))
+}
+
+/// JSONの本文を読み込めなかったときのエラーです．大きすぎる場合は413，Content-Typeが違う場合は415，それ以外は400を返します．
+fn json_error(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    let status = match &err {
+        JsonPayloadError::OverflowKnownLength { .. } | JsonPayloadError::Overflow { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        JsonPayloadError::ContentType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    let response = error_json(status, &err);
+    InternalError::from_response(err, response).into()
+}
+
+/// クエリ文字列を読み込めなかったときのエラーです．
+fn query_error(err: QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    let response = error_json(StatusCode::BAD_REQUEST, &err);
+    InternalError::from_response(err, response).into()
+}
+
+/// パスの一部(ジョブのIDなど)を読み込めなかったときのエラーです．
+fn path_error(err: PathError, _req: &HttpRequest) -> actix_web::Error {
+    let response = error_json(StatusCode::BAD_REQUEST, &err);
+    InternalError::from_response(err, response).into()
+}
+
+/// 本文の大きさの上限と，JSON，クエリ文字列，パスを読み込めなかったときのエラーを設定します．
+fn extractor_configs(cfg: &mut web::ServiceConfig, max_payload_bytes: usize) {
+    cfg.app_data(
+        web::JsonConfig::default()
+            .limit(max_payload_bytes)
+            .error_handler(json_error),
+    )
+    .app_data(web::QueryConfig::default().error_handler(query_error))
+    .app_data(web::PathConfig::default().error_handler(path_error))
+    .app_data(web::PayloadConfig::new(max_payload_bytes));
+}
+
+/// ブラウザからのアクセスを許可するオリジンを設定します．"*"ならすべて許可し，空なら他のオリジンからは使えません．
+fn cors(origins: &[String]) -> Cors {
+    let cors = Cors::default()
+        .allowed_methods(["GET", "POST", "DELETE"])
+        .allowed_header(header::CONTENT_TYPE)
+        .max_age(3600);
+    if origins.iter().any(|x| x == "*") {
+        return cors.allow_any_origin();
+    }
+    origins.iter().fold(cors, |cors, origin| cors.allowed_origin(origin))
+}
+
+/// 1回分の手札の記録です．
+/// 手札のID，"As"のような表記，役，得点
+#[derive(Serialize)]
+struct HandTrace {
+    cards: [u32; 5],
+    notation: Vec<String>,
+    role: Role,
+    score: i64,
+}
+
+/// 期待スコアの推定の精度です．
+/// 目標の相対精度，信頼度，実際に届いた相対精度(平均が0の場合はnull)，目標に届いたかどうか
+#[derive(Serialize)]
+struct PrecisionReport {
+    target: f64,
+    confidence: f64,
+    achieved: Option<f64>,
+    converged: bool,
 }
 
 /// 実行結果を保存する構造体です．
-/// 総スコア，回数，それぞれの役の出現回数
+/// 総スコア，回数，それぞれの役の出現回数，手札の記録(traceを指定した場合のみ)，
+/// 使ったカードの表記(namesを指定した場合のみ)，スコアの分布(distributionを指定した場合のみ)，
+/// 期待スコアの推定の精度(precisionを指定した場合のみ)
 #[derive(Serialize)]
 #[allow(non_snake_case)]
 pub struct Response {
-    allscore: u32,
+    allscore: i64,
+    number: u32,
+    result: RoleCounts,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace: Option<Vec<HandTrace>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cardNames: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    distribution: Option<Distribution>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    precision: Option<PrecisionReport>,
+}
+
+/// カード1枚ごとの分析結果です．
+/// AnalyzeResponse構造体の一部分でもあります．
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct CardResult {
+    id: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    hands: u32,
+    result: RoleCounts,
+    meanScore: f64,
+    removedDelta: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    swaps: Option<Vec<SwapResult>>,
+}
+
+/// カードを入れ替えたときの変化です．入れ替え先のカードと，1回あたりの期待スコアの変化
+#[derive(Serialize)]
+struct SwapResult {
+    id: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    delta: f64,
+}
+
+/// /analyze の実行結果を保存する構造体です．
+/// 回数，1回あたりの平均スコア，カードごとの分析結果
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+pub struct AnalyzeResponse {
+    number: u32,
+    meanScore: f64,
+    cards: Vec<CardResult>,
+}
+
+/// /compare でのデッキ1つ分の結果です．
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct CompareDeck {
+    allscore: i64,
+    meanScore: f64,
+    result: RoleCounts,
+}
+
+/// /compare での，1つ目のデッキとの差と検定結果です．
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct CompareDiff {
+    deck: usize,
+    result: BTreeMap<Role, i64>,
+    scoreDelta: i64,
+    meanDelta: f64,
+    stdError: f64,
+    zScore: Option<f64>,
+    pValue: f64,
+    significant: bool,
+}
+
+/// /compare の実行結果を保存する構造体です．
+/// 回数，使ったシード，デッキごとの結果，1つ目のデッキとの差
+#[derive(Serialize)]
+pub struct CompareResponse {
+    number: u32,
+    seed: u64,
+    decks: Vec<CompareDeck>,
+    diffs: Vec<CompareDiff>,
+}
+
+/// /showdown でのプレイヤー1人分の結果です．
+/// 総スコア，勝った回数，役ごとの回数，勝ったときの役ごとの回数
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct ShowdownSide {
+    allscore: i64,
+    wins: u32,
+    result: RoleCounts,
+    winsByRole: RoleCounts,
+}
+
+/// /showdown の実行結果を保存する構造体です．
+/// 回数，使ったシード，1人目から見た勝ち・負け・引き分けの割合，引き分けの回数，プレイヤーごとの結果
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+pub struct ShowdownResponse {
+    number: u32,
+    seed: u64,
+    winRate: f64,
+    loseRate: f64,
+    tieRate: f64,
+    ties: u32,
+    players: Vec<ShowdownSide>,
+}
+
+/// /table での席1つ分の結果です．
+/// 総スコア，1人だけで一番強かった回数，同じ強さで一番強かった回数，それぞれの割合，役ごとの回数
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct TableSeat {
+    allscore: i64,
+    wins: u32,
+    ties: u32,
+    winRate: f64,
+    tieRate: f64,
+    result: RoleCounts,
+}
+
+/// /table の実行結果を保存する構造体です．
+/// 回数，使ったシード，席ごとの結果
+#[derive(Serialize)]
+pub struct TableResponse {
+    number: u32,
+    seed: u64,
+    seats: Vec<TableSeat>,
+}
+
+/// /estimate の実行結果を保存する構造体です．
+/// 推定の方法，実際に配った手札の数，使ったシード，1回あたりの期待スコアとその標準誤差，
+/// 有効サンプルサイズ(推定の分散が0の場合はnull)，役ごとの確率
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+pub struct EstimateResponse {
+    strategy: porker::sampling::Strategy,
     number: u32,
-    result: ResultRole,
+    seed: u64,
+    meanScore: f64,
+    stdError: f64,
+    effectiveSampleSize: f64,
+    probabilities: BTreeMap<Role, f64>,
+}
+
+/// /sessions でのセッション1回分の結果です．
+/// 何回目か，参加費を引いた合計スコア，配られた手札の数，破産したかどうか，役ごとの回数
+#[derive(Serialize)]
+struct SessionOutcome {
+    index: u32,
+    total: i64,
+    hands: u32,
+    ruined: bool,
+    result: RoleCounts,
+}
+
+/// /sessions の実行結果を保存する構造体です．
+/// セッションの回数，1セッションの手札の数，使ったシード，合計スコアの平均・分散・パーセンタイル，
+/// 破産したセッションの割合(bankrollを指定した場合のみ)，一番良かったセッションと悪かったセッション
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+pub struct SessionResponse {
+    sessions: u32,
+    hands: u32,
+    seed: u64,
+    mean: f64,
+    variance: f64,
+    percentiles: Percentiles,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    riskOfRuin: Option<f64>,
+    best: SessionOutcome,
+    worst: SessionOutcome,
+}
+
+/// デイリーチャレンジのルールです．
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct ChallengeRules {
+    constraints: DeckConstraints,
+    bonusSuit: porker::Suit,
+    bonusMultiplier: i64,
+}
+
+/// /challenge/today の実行結果を保存する構造体です．
+/// 日付，シード，配る手札の数，ルール
+#[derive(Serialize)]
+pub struct ChallengeResponse {
+    date: String,
+    seed: u64,
+    number: u32,
+    rules: ChallengeRules,
+}
+
+/// /challenge/{date}/submit の実行結果を保存する構造体です．
+/// 日付，名前，総スコア，回数，それぞれの役の出現回数，順位，ランキング
+#[derive(Serialize)]
+pub struct ChallengeSubmitResponse {
+    date: String,
+    name: String,
+    allscore: i64,
+    number: u32,
+    result: RoleCounts,
+    rank: u32,
+    leaderboard: Vec<database::LeaderboardEntry>,
+}
+
+/// /evaluate での手札1つ分の判定結果です．
+/// 手札のID，表記，役，同じ役同士で比べるためのランク(tiebreak)とそれをまとめた値(value)，
+/// スコア，役を作っているカードのID
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct HandEvaluation {
+    cards: [u32; 5],
+    notation: Vec<String>,
+    role: Role,
+    tiebreak: Vec<u32>,
+    value: u32,
+    score: i64,
+    roleCards: Vec<u32>,
+}
+
+/// /evaluate の実行結果を保存する構造体です．
+#[derive(Serialize)]
+pub struct EvaluateResponse {
+    hands: Vec<HandEvaluation>,
 }
 
 ///必要なデータを渡すと，レスポンスを生成します．
 impl Response {
-    fn new(all_score: u32, number: u32, role_count: [u32; 10]) -> Response {
+    fn new(all_score: i64, number: u32, role_count: RoleCounts) -> Response {
         Response {
             allscore: all_score,
             number,
-            result: ResultRole {
-                nopair: role_count[0],
-                onepair: role_count[1],
-                twopair: role_count[2],
-                threepair: role_count[3],
-                fourpair: role_count[4],
-                fulhouse: role_count[5],
-                flush: role_count[6],
-                strait: role_count[7],
-                straitflush: role_count[8],
-                royalflush: role_count[9],
+            result: role_count,
+            trace: None,
+            cardNames: None,
+            distribution: None,
+            precision: None,
+        }
+    }
+
+    /// 使ったカードの表記を付け加えます．
+    fn with_card_names(mut self, use_cards: &[u32]) -> Response {
+        self.cardNames = Some(use_cards.iter().map(|id| porker::Card::new(*id).to_string()).collect());
+        self
+    }
+
+    /// 手札の記録を付け加えます．
+    fn with_trace(mut self, traces: Vec<porker::HandTrace>) -> Response {
+        self.trace = Some(
+            traces
+                .iter()
+                .map(|x| HandTrace {
+                    cards: x.cards,
+                    notation: x.cards.iter().map(|id| porker::Card::new(*id).to_string()).collect(),
+                    role: x.role,
+                    score: x.score,
+                })
+                .collect(),
+        );
+        self
+    }
+
+    /// スコアの分布を付け加えます．
+    fn with_distribution(mut self, distribution: Distribution) -> Response {
+        self.distribution = Some(distribution);
+        self
+    }
+
+    /// 期待スコアの推定の精度を付け加えます．
+    fn with_precision(mut self, convergence: porker::convergence::Convergence) -> Response {
+        self.precision = Some(PrecisionReport {
+            target: convergence.target,
+            confidence: convergence.confidence,
+            achieved: convergence.achieved,
+            converged: convergence.converged,
+        });
+        self
+    }
+}
+
+///分析結果から，レスポンスを生成します．
+/// namesがtrueの場合は，カードごとの結果にカードの表記を含めます．
+impl AnalyzeResponse {
+    fn new(analysis: porker::analysis::Analysis, names: bool) -> AnalyzeResponse {
+        AnalyzeResponse {
+            number: analysis.loop_num,
+            meanScore: analysis.mean_score,
+            cards: analysis
+                .cards
+                .iter()
+                .map(|x| CardResult {
+                    id: x.id,
+                    name: names.then(|| porker::Card::new(x.id).to_string()),
+                    hands: x.hands,
+                    result: x.role_count,
+                    meanScore: x.mean_score,
+                    removedDelta: x.removed_delta,
+                    swaps: x.swaps.as_ref().map(|swaps| {
+                        swaps
+                            .iter()
+                            .map(|swap| SwapResult {
+                                id: swap.id,
+                                name: names.then(|| porker::Card::new(swap.id).to_string()),
+                                delta: swap.delta,
+                            })
+                            .collect()
+                    }),
+                })
+                .collect(),
+        }
+    }
+}
+
+///対戦結果から，レスポンスを生成します．
+impl ShowdownResponse {
+    fn new(showdown: porker::showdown::Showdown) -> ShowdownResponse {
+        let (win_rate, lose_rate, tie_rate) = showdown.rates();
+        ShowdownResponse {
+            number: showdown.loop_num,
+            seed: showdown.seed,
+            winRate: win_rate,
+            loseRate: lose_rate,
+            tieRate: tie_rate,
+            ties: showdown.ties,
+            players: showdown
+                .sides
+                .iter()
+                .map(|x| ShowdownSide {
+                    allscore: x.sum_score,
+                    wins: x.wins,
+                    result: x.role_count,
+                    winsByRole: x.wins_by_role,
+                })
+                .collect(),
+        }
+    }
+}
+
+///テーブルの結果から，レスポンスを生成します．
+impl TableResponse {
+    fn new(table: porker::table::Table) -> TableResponse {
+        let rate = |count: u32| {
+            if table.loop_num == 0 {
+                0.
+            } else {
+                count as f64 / table.loop_num as f64
+            }
+        };
+        TableResponse {
+            number: table.loop_num,
+            seed: table.seed,
+            seats: table
+                .seats
+                .iter()
+                .map(|x| TableSeat {
+                    allscore: x.sum_score,
+                    wins: x.wins,
+                    ties: x.ties,
+                    winRate: rate(x.wins),
+                    tieRate: rate(x.ties),
+                    result: x.role_count,
+                })
+                .collect(),
+        }
+    }
+}
+
+///推定の結果から，レスポンスを生成します．
+impl EstimateResponse {
+    fn new(estimate: porker::sampling::Estimate) -> EstimateResponse {
+        EstimateResponse {
+            strategy: estimate.strategy,
+            number: estimate.loop_num,
+            seed: estimate.seed,
+            meanScore: estimate.mean_score,
+            stdError: estimate.std_error,
+            effectiveSampleSize: estimate.effective_sample_size,
+            probabilities: estimate.probabilities,
+        }
+    }
+}
+
+impl SessionOutcome {
+    fn new(result: porker::session::SessionResult) -> SessionOutcome {
+        SessionOutcome {
+            index: result.index,
+            total: result.total,
+            hands: result.hands,
+            ruined: result.ruined,
+            result: result.role_count,
+        }
+    }
+}
+
+///セッションの集計から，レスポンスを生成します．
+impl SessionResponse {
+    fn new(summary: porker::session::SessionSummary) -> SessionResponse {
+        SessionResponse {
+            sessions: summary.sessions,
+            hands: summary.hands,
+            seed: summary.seed,
+            mean: summary.mean,
+            variance: summary.variance,
+            percentiles: summary.percentiles,
+            riskOfRuin: summary.risk_of_ruin,
+            best: SessionOutcome::new(summary.best),
+            worst: SessionOutcome::new(summary.worst),
+        }
+    }
+}
+
+///チャレンジから，レスポンスを生成します．
+impl ChallengeResponse {
+    fn new(challenge: porker::challenge::Challenge) -> ChallengeResponse {
+        ChallengeResponse {
+            date: challenge.date.to_string(),
+            seed: challenge.seed,
+            number: porker::challenge::CHALLENGE_LOOP_NUM,
+            rules: ChallengeRules {
+                constraints: challenge.rules.constraints,
+                bonusSuit: challenge.rules.bonus_suit,
+                bonusMultiplier: challenge.rules.bonus_multiplier,
             },
         }
     }
 }
 
+///判定結果から，レスポンスを生成します．
+impl EvaluateResponse {
+    fn new(evaluations: Vec<porker::Evaluation>) -> EvaluateResponse {
+        EvaluateResponse {
+            hands: evaluations
+                .into_iter()
+                .map(|x| HandEvaluation {
+                    cards: x.cards,
+                    notation: x.cards.iter().map(|id| porker::Card::new(*id).to_string()).collect(),
+                    role: x.rank.role,
+                    value: x.rank.value(),
+                    tiebreak: x.rank.kickers,
+                    score: x.score,
+                    roleCards: x.role_cards.iter().map(|card| card.id).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+///比較結果から，レスポンスを生成します．
+impl CompareResponse {
+    fn new(comparison: porker::compare::Comparison) -> CompareResponse {
+        CompareResponse {
+            number: comparison.loop_num,
+            seed: comparison.seed,
+            decks: comparison
+                .decks
+                .iter()
+                .map(|x| CompareDeck {
+                    allscore: x.sum_score,
+                    meanScore: x.mean_score,
+                    result: x.role_count,
+                })
+                .collect(),
+            diffs: comparison
+                .diffs
+                .iter()
+                .map(|x| CompareDiff {
+                    deck: x.deck,
+                    result: x.role_diff.clone(),
+                    scoreDelta: x.score_delta,
+                    meanDelta: x.mean_delta,
+                    stdError: x.std_error,
+                    zScore: x.z_score,
+                    pValue: x.p_value,
+                    significant: x.significant,
+                })
+                .collect(),
+        }
+    }
+}
+
 /// 使うカードのデータをPOSTすると，指定回数ランダムに手札を取り出し役判定します．
 /// スコア計算も行い，レスポンスを返します．
 /// 実行時間の都合上，最大回数を100万回に制限しています．
+/// traceを指定した場合は，配られた手札を1つずつ記録して返します．この場合の最大回数は1000回です．
+/// distributionを指定した場合は，手札ごとのスコアとバッチごとの合計スコアの分布も返します．
+/// precisionを指定した場合は，期待スコアの推定がその相対精度に届くまで(最大num回)実行し，実際の回数と精度を返します．
+/// 実行結果はデータベースに保存し，/runsで見られます．
 #[post["/postcards"]]
-async fn judge_porker(request: web::Json<Request>) -> impl Responder {
-    match porker::million_porker(&request.useCards, request.num) {
-        Ok((role_count, sum_score, loop_num)) => {
-            porker::debug_judge_role(&role_count, loop_num);
-            HttpResponse::Ok().json(Response::new(sum_score, loop_num, role_count))
+async fn judge_porker(
+    request: web::Json<Request>,
+    constraints: web::Data<DeckConstraints>,
+    sheet: web::Data<ScoreSheet>,
+    pool: web::Data<SqlitePool>,
+) -> impl Responder {
+    if let Err(e) = constraints.validate(&request.useCards) {
+        return bad_request(e);
+    }
+
+    if [request.trace, request.distribution, request.precision.is_some()]
+        .iter()
+        .filter(|x| **x)
+        .count()
+        > 1
+    {
+        return error_json(
+            StatusCode::BAD_REQUEST,
+            "Error: trace, distribution and precision cannot be used together",
+        );
+    }
+
+    let request = request.into_inner();
+    let use_cards = request.useCards.clone();
+    let names = request.names;
+    let response = web::block(move || run_postcards(&request, &sheet)).await;
+    let response = match response {
+        Ok(response) => response,
+        Err(e) => return error_json(StatusCode::INTERNAL_SERVER_ERROR, e),
+    };
+
+    if let Ok(response) = &response {
+        let saved = database::insert_millionpoker(
+            &pool,
+            "postcards",
+            &use_cards,
+            response.number,
+            &response.result,
+            response.allscore,
+        )
+        .await;
+        // 保存に失敗しても結果は返す
+        if let Err(e) = saved {
+            log::warn!("failed to save run: {}", e);
+        }
+    }
+
+    match response {
+        Ok(response) if names => HttpResponse::Ok().json(response.with_card_names(&use_cards)),
+        Ok(response) => HttpResponse::Ok().json(response),
+        Err(e) => bad_request(e),
+    }
+}
+
+/// /postcardsのシミュレーションを，指定された方法で実行します．
+fn run_postcards(request: &Request, sheet: &ScoreSheet) -> anyhow::Result<Response> {
+    if request.trace {
+        porker::trace_porker(&request.useCards, request.num, sheet).map(
+            |(role_count, sum_score, loop_num, traces)| {
+                Response::new(sum_score, loop_num, role_count).with_trace(traces)
+            },
+        )
+    } else if let Some(precision) = request.precision {
+        porker::convergence::converge_porker(
+            &request.useCards,
+            precision,
+            request.confidence.unwrap_or(porker::convergence::DEFAULT_CONFIDENCE),
+            request.num,
+            sheet,
+        )
+        .map(|(role_count, sum_score, loop_num, convergence)| {
+            Response::new(sum_score, loop_num, role_count).with_precision(convergence)
+        })
+    } else if request.distribution {
+        porker::distribution::distribution_porker(
+            &request.useCards,
+            request.num,
+            sheet,
+            request.batchSize,
+            &mut rand::thread_rng(),
+        )
+        .map(|(role_count, sum_score, loop_num, distribution)| {
+            Response::new(sum_score, loop_num, role_count).with_distribution(distribution)
+        })
+    } else {
+        porker::million_porker(&request.useCards, request.num, sheet).map(|(role_count, sum_score, loop_num)| {
+            porker::debug_judge_role(&role_count);
+            Response::new(sum_score, loop_num, role_count)
+        })
+    }
+}
+
+/// 使うカードのデータをPOSTすると，カード1枚ごとに，そのカードが入った手札の役の出現回数と，
+/// そのカードを抜いたときの1回あたりの期待スコアの変化を返します．
+/// swapを指定した場合は，デッキに無いカードと入れ替えたときの変化も返します．この場合の回数の上限は10万回です．
+/// 回数の上限は/postcardsと同じです．
+#[post["/analyze"]]
+async fn analyze_cards(
+    request: web::Json<Request>,
+    constraints: web::Data<DeckConstraints>,
+    sheet: web::Data<ScoreSheet>,
+) -> impl Responder {
+    if let Err(e) = constraints.validate(&request.useCards) {
+        return bad_request(e);
+    }
+
+    let names = request.names;
+    simulate(
+        move || porker::analysis::analyze_cards(&request.useCards, request.num, &sheet, request.swap),
+        |analysis| HttpResponse::Ok().json(AnalyzeResponse::new(analysis, names)),
+    )
+    .await
+}
+
+/// 2つ以上のデッキを，同じ回数，同じ乱数列で実行して比べます．
+/// 1つ目のデッキを基準に，役ごとの回数の差，スコアの差と，その差が有意かどうかを返します．
+#[post["/compare"]]
+async fn compare_decks(
+    request: web::Json<CompareRequest>,
+    constraints: web::Data<DeckConstraints>,
+    sheet: web::Data<ScoreSheet>,
+) -> impl Responder {
+    if let Err(e) = validate_decks(&constraints, &request.decks) {
+        return bad_request(e);
+    }
+
+    simulate(
+        move || porker::compare::compare_decks(&request.decks, request.num, request.seed, &sheet),
+        |comparison| HttpResponse::Ok().json(CompareResponse::new(comparison)),
+    )
+    .await
+}
+
+/// 2人のデッキからそれぞれ手札を配り，役とキッカーまで含めた強さで対戦させます．
+/// 1人目から見た勝ち・負け・引き分けの割合と，プレイヤーごとの役の出現回数を返します．回数の上限は/postcardsと同じです．
+#[post["/showdown"]]
+async fn showdown_decks(
+    request: web::Json<ShowdownRequest>,
+    constraints: web::Data<DeckConstraints>,
+    sheet: web::Data<ScoreSheet>,
+) -> impl Responder {
+    if let Err(e) = validate_decks(&constraints, &request.decks) {
+        return bad_request(e);
+    }
+
+    simulate(
+        move || porker::showdown::showdown(&request.decks, request.num, request.seed, &sheet),
+        |showdown| HttpResponse::Ok().json(ShowdownResponse::new(showdown)),
+    )
+    .await
+}
+
+/// 1つのデッキを混ぜて，players人に重ならないように手札を配って対戦させます．
+/// 席ごとに，役の出現回数と一番強い手札だった回数を返します．回数の上限は/postcardsと同じです．
+#[post["/table"]]
+async fn deal_table(
+    request: web::Json<TableRequest>,
+    constraints: web::Data<DeckConstraints>,
+    sheet: web::Data<ScoreSheet>,
+) -> impl Responder {
+    if let Err(e) = validate_decks(&constraints, std::slice::from_ref(&request.useCards)) {
+        return bad_request(e);
+    }
+
+    simulate(
+        move || porker::table::deal_table(&request.useCards, request.players, request.num, request.seed, &sheet),
+        |table| HttpResponse::Ok().json(TableResponse::new(table)),
+    )
+    .await
+}
+
+/// 分散を小さくする方法で，1回あたりの期待スコアと役ごとの確率を推定します．
+/// 標準誤差と，同じ精度を普通に配って出すのに必要な回数(有効サンプルサイズ)も返します．回数の上限は/postcardsと同じです．
+#[post["/estimate"]]
+async fn estimate_porker(
+    request: web::Json<EstimateRequest>,
+    constraints: web::Data<DeckConstraints>,
+    sheet: web::Data<ScoreSheet>,
+) -> impl Responder {
+    if let Err(e) = constraints.validate(&request.useCards) {
+        return bad_request(e);
+    }
+
+    simulate(
+        move || porker::sampling::estimate_porker(&request.useCards, request.num, request.strategy, request.seed, &sheet),
+        |estimate| HttpResponse::Ok().json(EstimateResponse::new(estimate)),
+    )
+    .await
+}
+
+/// JSONLかCSVで書いたデッキの一覧をPOSTすると，デッキごとにシミュレーションし，結果を1行ずつ返します．
+/// デッキは設定のjobWorkers(省略するとCPUのスレッド数)だけ並列に実行します．失敗した行は，その行のerrorに理由を入れて返します．
+#[post["/batch"]]
+async fn batch_porker(
+    body: String,
+    query: web::Query<BatchQuery>,
+    constraints: web::Data<DeckConstraints>,
+    sheet: web::Data<ScoreSheet>,
+    config: web::Data<config::Config>,
+) -> impl Responder {
+    let input = query.input.unwrap_or_else(|| porker::batch::BatchFormat::detect(&body));
+    let output = query.output.unwrap_or(input);
+    let rows = match porker::batch::parse_batch(&body, input) {
+        Ok(rows) => rows,
+        Err(e) => return bad_request(e),
+    };
+
+    let workers = config.job_workers();
+    let results = web::block(move || porker::batch::run_batch(&rows, &sheet, &constraints, workers)).await;
+    match results {
+        Ok(results) => {
+            let content_type = match output {
+                porker::batch::BatchFormat::Jsonl => "application/x-ndjson",
+                porker::batch::BatchFormat::Csv => "text/csv; charset=utf-8",
+            };
+            HttpResponse::Ok()
+                .content_type(content_type)
+                .body(porker::batch::write_batch(&results, output))
         }
-        Err(e) => HttpResponse::BadRequest().body(format!("{}", e)),
+        Err(e) => error_json(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+/// 手札hands回を1セッションとして，セッションをsessions回繰り返します．
+/// 手札ごとにanteを払い，bankrollを指定した場合は持ち点が尽きたセッションを破産として数えます．
+/// 合計スコアの分布，破産の確率，一番良かったセッションと悪かったセッションを返します．手札の総数の上限は/postcardsと同じです．
+#[post["/sessions"]]
+async fn simulate_sessions(
+    request: web::Json<SessionRequest>,
+    constraints: web::Data<DeckConstraints>,
+    sheet: web::Data<ScoreSheet>,
+) -> impl Responder {
+    if let Err(e) = constraints.validate(&request.useCards) {
+        return bad_request(e);
+    }
+
+    let config = porker::session::SessionConfig {
+        hands: request.hands,
+        sessions: request.sessions,
+        ante: request.ante,
+        bankroll: request.bankroll,
+        seed: request.seed,
+    };
+    simulate(
+        move || porker::session::simulate_sessions(&request.useCards, &config, &sheet),
+        |summary| HttpResponse::Ok().json(SessionResponse::new(summary)),
+    )
+    .await
+}
+
+/// 保存したシミュレーションの実行結果を新しい順に返します．
+/// kind，from，to(UNIX時間)，minNum，card(カードのID)で絞り込み，limitとoffsetで範囲を指定できます．
+#[get["/runs"]]
+async fn list_runs(filter: web::Query<RunFilter>, pool: web::Data<SqlitePool>) -> impl Responder {
+    let mut filter = filter.into_inner();
+    filter.limit = Some(filter.limit.unwrap_or(DEFAULT_RUNS_LIMIT).min(MAX_RUNS_LIMIT));
+
+    match database::list_runs(&pool, &filter).await {
+        Ok(runs) => HttpResponse::Ok().json(runs),
+        Err(e) => error_json(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+/// /runsと同じ絞り込みで，保存した実行結果をすべてCSVかJSONLで書き出します．
+/// limitを指定しなければ件数は制限しません．SQLiteから1行ずつ読みながら返すので，件数が多くてもメモリに溜めません．
+#[get["/runs/export"]]
+async fn export_runs(
+    filter: web::Query<RunFilter>,
+    query: web::Query<ExportQuery>,
+    pool: web::Data<SqlitePool>,
+) -> impl Responder {
+    let format = query.format.unwrap_or(porker::batch::BatchFormat::Csv);
+    let content_type = match format {
+        porker::batch::BatchFormat::Jsonl => "application/x-ndjson",
+        porker::batch::BatchFormat::Csv => "text/csv; charset=utf-8",
+    };
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .streaming(export::start(pool.get_ref().clone(), filter.into_inner(), format))
+}
+
+/// 今日(UTC)のデイリーチャレンジのルールとシードを返します．
+#[get["/challenge/today"]]
+async fn challenge_today() -> impl Responder {
+    let challenge = porker::challenge::Challenge::new(porker::challenge::Date::today());
+    HttpResponse::Ok().json(ChallengeResponse::new(challenge))
+}
+
+/// その日のデイリーチャレンジにデッキを提出します．全員が同じ乱数列で採点され，結果はランキングに保存されます．
+/// 未来の日付のチャレンジには提出できません．
+#[post["/challenge/{date}/submit"]]
+async fn challenge_submit(
+    date: web::Path<String>,
+    request: web::Json<ChallengeSubmitRequest>,
+    pool: web::Data<SqlitePool>,
+) -> impl Responder {
+    let date: porker::challenge::Date = match date.parse() {
+        Ok(date) => date,
+        Err(e) => return bad_request(e),
+    };
+    if date > porker::challenge::Date::today() {
+        return error_json(StatusCode::BAD_REQUEST, "Error: Challenge is not open yet");
+    }
+    let name = request.name.trim();
+    if name.is_empty() {
+        return error_json(StatusCode::BAD_REQUEST, "Error: name is required");
+    }
+
+    let use_cards = request.useCards.clone();
+    let result = match web::block(move || porker::challenge::Challenge::new(date).score(&use_cards)).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => return bad_request(e),
+        Err(e) => return error_json(StatusCode::INTERNAL_SERVER_ERROR, e),
+    };
+
+    let date = date.to_string();
+    let saved = async {
+        database::insert_challenge_entry(&pool, &date, name, &request.useCards, result.score).await?;
+        let rank = database::challenge_rank(&pool, &date, result.score).await?;
+        let leaderboard = database::challenge_leaderboard(&pool, &date, LEADERBOARD_SIZE).await?;
+        Ok::<_, Box<dyn std::error::Error>>((rank, leaderboard))
+    };
+
+    match saved.await {
+        Ok((rank, leaderboard)) => HttpResponse::Ok().json(ChallengeSubmitResponse {
+            date,
+            name: name.to_string(),
+            allscore: result.score,
+            number: porker::challenge::CHALLENGE_LOOP_NUM,
+            result: result.role_count,
+            rank,
+            leaderboard,
+        }),
+        Err(e) => error_json(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+/// その日のデイリーチャレンジのランキングを返します．
+#[get["/challenge/{date}/leaderboard"]]
+async fn challenge_leaderboard(date: web::Path<String>, pool: web::Data<SqlitePool>) -> impl Responder {
+    let date: porker::challenge::Date = match date.parse() {
+        Ok(date) => date,
+        Err(e) => return bad_request(e),
+    };
+
+    match database::challenge_leaderboard(&pool, &date.to_string(), LEADERBOARD_SIZE).await {
+        Ok(leaderboard) => HttpResponse::Ok().json(leaderboard),
+        Err(e) => error_json(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+/// 指定した手札の役を判定します．手札1つ(hand)か，複数の手札(hands)を受け取ります．
+/// 役，同じ役同士の強さ，スコア，役を作っているカードを返します．
+#[post["/evaluate"]]
+async fn evaluate_hands(request: web::Json<EvaluateRequest>, sheet: web::Data<ScoreSheet>) -> impl Responder {
+    let hands = match request.into_inner() {
+        EvaluateRequest::One { hand } => vec![hand],
+        EvaluateRequest::Many { hands } => hands,
+    };
+
+    simulate(
+        move || porker::evaluate_hands(&hands, &sheet),
+        |evaluations| HttpResponse::Ok().json(EvaluateResponse::new(evaluations)),
+    )
+    .await
+}
+
+/// /postcardsと同じデータをPOSTすると，途中経過をServer-Sent Eventsで送りながらシミュレーションします．
+/// 途中経過はprogressイベント，最後の結果はresultイベントで，どちらも/postcardsと同じ形式のJSONです．
+#[post["/postcards/stream"]]
+async fn stream_porker(
+    request: web::Json<stream::StreamRequest>,
+    constraints: web::Data<DeckConstraints>,
+    sheet: web::Data<ScoreSheet>,
+) -> impl Responder {
+    if let Err(e) = constraints.validate(request.use_cards()) {
+        return bad_request(e);
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(stream::start(request.into_inner(), sheet.get_ref().clone()))
+}
+
+/// /postcardsと同じデータをPOSTすると，シミュレーションをジョブとして登録し，すぐにジョブの状態を返します．
+/// 結果はGET /jobs/{id} で受け取ります．回数の上限は1億回です．
+#[post["/jobs"]]
+async fn create_job(
+    request: web::Json<Request>,
+    queue: web::Data<jobs::JobQueue>,
+    constraints: web::Data<DeckConstraints>,
+    sheet: web::Data<ScoreSheet>,
+) -> impl Responder {
+    if let Err(e) = constraints.validate(&request.useCards) {
+        return bad_request(e);
+    }
+
+    // ジョブは合計スコアと役の出現回数だけを返すので，他の実行方法は受け付けない
+    if request.trace || request.distribution || request.precision.is_some() {
+        return error_json(
+            StatusCode::BAD_REQUEST,
+            "Error: trace, distribution and precision cannot be used with jobs",
+        );
+    }
+
+    let request = request.into_inner();
+    match queue.submit(request.useCards, request.num, sheet.get_ref().clone()) {
+        Some(job) => HttpResponse::Accepted().json(job),
+        None => error_json(StatusCode::SERVICE_UNAVAILABLE, "Error: Job queue is full"),
     }
 }
 
+/// ジョブの状態，進捗，終了していれば結果を返します．
+#[get["/jobs/{id}"]]
+async fn get_job(id: web::Path<u64>, queue: web::Data<jobs::JobQueue>) -> impl Responder {
+    match queue.get(*id) {
+        Some(job) => HttpResponse::Ok().json(job),
+        None => error_json(StatusCode::NOT_FOUND, "Error: Job not found"),
+    }
+}
+
+/// 実行待ちか実行中のジョブをキャンセルします．終了済みのジョブの場合は結果を削除します．
+#[delete["/jobs/{id}"]]
+async fn delete_job(id: web::Path<u64>, queue: web::Data<jobs::JobQueue>) -> impl Responder {
+    match queue.cancel(*id) {
+        Some(job) => HttpResponse::Ok().json(job),
+        None => error_json(StatusCode::NOT_FOUND, "Error: Job not found"),
+    }
+}
+
+/// 対戦ルームのWebSocketに接続します．メッセージの形式はroomsモジュールを見てください．
+#[get["/ws"]]
+async fn room_ws(
+    req: HttpRequest,
+    body: web::Payload,
+    hub: web::Data<rooms::Hub>,
+) -> actix_web::Result<HttpResponse> {
+    let (response, session, stream) = actix_ws::handle(&req, body)?;
+    actix_web::rt::spawn(rooms::run(hub.into_inner(), session, stream));
+    Ok(response)
+}
+
 ///テスト用の関数です．特に意味はありません．helloを返します．
 #[get["/"]]
 async fn get_index() -> impl Responder {
@@ -101,55 +1276,82 @@ async fn una() -> impl Responder {
 ///エントリーポイントです．
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
-    log::info!("starting HTTP server at http://localhost:5000");
+    let config = config::Config::load().map_err(|e| std::io::Error::other(e.to_string()))?;
+    env_logger::init_from_env(env_logger::Env::new().default_filter_or(&config.log_level));
+    log::info!("config: {}", serde_json::to_string(&config).unwrap_or_default());
+    log::info!("starting HTTP server at http://{}:{}", config.host, config.port);
+    porker::set_limits(config.limits);
+
+    let job_queue = web::Data::new(jobs::JobQueue::new(config.job_workers(), config.job_max_loop_num, config.job_max_pending));
+
+    // 対戦結果の保存に使うデータベース
+    let pool = database::create_sqlite_pool(&config.database_url)
+        .await
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    database::migrate_database(&pool)
+        .await
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    let pool = web::Data::new(pool);
 
-    HttpServer::new(|| {
+    // デッキの制約．指定が無ければ制限しない
+    let constraints = match &config.deck_constraints {
+        Some(path) => DeckConstraints::load(path)
+            .map_err(|e| std::io::Error::other(format!("{}: {}", path, e)))?,
+        None => DeckConstraints::default(),
+    };
+    let constraints = web::Data::new(constraints);
+
+    // スコアシート．指定が無ければ役ごとの固定のスコアだけを使う
+    let sheet = match &config.score_sheet {
+        Some(path) => ScoreSheet::load(path).map_err(|e| std::io::Error::other(format!("{}: {}", path, e)))?,
+        None => ScoreSheet::default(),
+    };
+    let sheet = web::Data::new(sheet);
+
+    let hub = web::Data::new(rooms::Hub::new(
+        pool.get_ref().clone(),
+        sheet.get_ref().clone(),
+        constraints.get_ref().clone(),
+    ));
+
+    let (host, port, workers) = (config.host.clone(), config.port, config.workers());
+    let server_config = web::Data::new(config);
+
+    HttpServer::new(move || {
+        let max_payload_bytes = server_config.max_payload_bytes;
         App::new()
+            .wrap(cors(&server_config.cors_origins))
+            .configure(|cfg| extractor_configs(cfg, max_payload_bytes))
+            .app_data(job_queue.clone())
+            .app_data(hub.clone())
+            .app_data(pool.clone())
+            .app_data(constraints.clone())
+            .app_data(sheet.clone())
+            .app_data(server_config.clone())
             .service(get_index)
             .service(judge_porker)
+            .service(stream_porker)
+            .service(analyze_cards)
+            .service(compare_decks)
+            .service(evaluate_hands)
+            .service(showdown_decks)
+            .service(deal_table)
+            .service(simulate_sessions)
+            .service(estimate_porker)
+            .service(batch_porker)
+            .service(list_runs)
+            .service(export_runs)
+            .service(create_job)
+            .service(get_job)
+            .service(delete_job)
+            .service(room_ws)
+            .service(challenge_today)
+            .service(challenge_submit)
+            .service(challenge_leaderboard)
             .service(una)
     })
-    .bind(("127.0.0.1", 5001))?
+    .workers(workers)
+    .bind((host, port))?
     .run()
     .await
 }
-
-//ここから下はデバッグ用に使ったコード
-/*
-fn main() {
-    let now = time::Instant::now();
-
-    let use_cards = porker::Card::all_cards_id();
-
-    let mut role_count = [0; 10];
-
-    let num = 1_000_000;
-
-    //let mut rng = rand_xoshiro::Xoshiro256StarStar::seed_from_u64(123);
-
-    /*
-    for _ in 0..1_000_000 {
-        let cards = porker::handout_cards(&use_cards);
-
-        //println!("{:?}", cards);
-
-        let mut cards = porker::make_cards_from_id(&cards);
-
-        //ソート結果を表示
-        //cards.sort_by(|a, b| a.rank.cmp(&b.rank));
-        //println!("{:?}", cards);
-
-        porker::count_judge_role(&mut cards, &mut role_count);
-    }
-    */
-
-    porker::million_porker(&use_cards, &mut role_count, num);
-
-    println!("{:?}", role_count);
-
-    porker::debug_judge_role(&role_count, 1_000_000);
-    println!("{:?}", now.elapsed());
-    //仮
-}
-*/
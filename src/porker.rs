@@ -1,17 +1,39 @@
-//! ポーカーモジュール 個々の役を判定する関数は，事前にrankの順にソートされていることを前提としています．
+//! ポーカーモジュール 役の判定はclassifyにまとまっていて，手札の並び順に関係なく正しい役を返します．
 
 // anyhow入れてみたはいいものの，あまり使い方がわからない
 // ? をもっと有効活用できそうなものだが...
-use rand::{thread_rng, Rng};
+use rand::{seq::SliceRandom, thread_rng, Rng};
 use rustc_hash::FxHashMap;
-use std::{convert::TryInto};
+use std::{convert::TryInto, fmt, sync::RwLock};
 use num_derive::FromPrimitive;
 use anyhow::{anyhow};
-
-//mod test;
+use serde::{Deserialize, Serialize};
+
+pub mod analysis;
+pub mod batch;
+pub mod challenge;
+pub mod compare;
+pub mod constraints;
+pub mod convergence;
+pub mod distribution;
+mod role_counts;
+pub mod sampling;
+pub mod scoring;
+pub mod session;
+pub mod showdown;
+pub mod table;
+pub mod stats;
+
+pub use role_counts::RoleCounts;
+pub use scoring::ScoreSheet;
+#[cfg(test)]
+mod test;
 
 ///カード1枚のデータを保持する構造体です．ID,スート(記号), ランク(数字)からできています．
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+/// JSONでは{"id": 0, "suit": "spade", "rank": 1}の形で書き出します．
+/// 読み込むときは，この形のほかに，IDの数値と"As"のような表記の文字列も受け付けます．
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(try_from = "CardInput")]
 pub struct Card {
     pub id: u32,
     pub suit: Suit,
@@ -20,7 +42,9 @@ pub struct Card {
 
 /// 記号情報を保持する列挙型です． NumクレートのFromPrimitivを活用することにより，u32型をSuit型に変換する機能を提供しています．
 /// num::FromPrimitive::from_u32(<u32>).unwrap() でu32型からSuit型に変換できます．
-#[derive(FromPrimitive, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+/// JSONでは"spade"のような小文字の英語名になります．
+#[derive(FromPrimitive, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
 #[repr(u32)]
 pub enum Suit {
     #[default]
@@ -30,22 +54,165 @@ pub enum Suit {
     Club,
 }
 
-#[derive(PartialEq, Eq)]
+/// 役を表す列挙型です．
+/// JSONでの名前は，フロントエンドで使っている/postcardsのレスポンスのキーに合わせています．
+/// 名前を変えるとクライアントが壊れるので，変えないでください．
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Role {
+    #[serde(rename = "nopair")]
     NoPair,
+    #[serde(rename = "onepair")]
     OnePair,
+    #[serde(rename = "twopair")]
     TwoPair,
+    #[serde(rename = "threepair")]
     ThreeCard,
+    #[serde(rename = "strait")]
     Straight,
+    #[serde(rename = "flush")]
     Flush,
+    #[serde(rename = "fulhouse")]
     FullHouse,
+    #[serde(rename = "fourpair")]
     FourCard,
+    #[serde(rename = "straitflush")]
     StraightFlush,
+    #[serde(rename = "royalflush")]
     RoyalStraightFlush,
 }
 
+impl Role {
+    /// 役の種類の数です．
+    pub const COUNT: usize = 10;
+
+    /// すべての役を，宣言した順(弱い順)に並べた配列です．
+    pub const ALL: [Role; Role::COUNT] = [
+        Role::NoPair,
+        Role::OnePair,
+        Role::TwoPair,
+        Role::ThreeCard,
+        Role::Straight,
+        Role::Flush,
+        Role::FullHouse,
+        Role::FourCard,
+        Role::StraightFlush,
+        Role::RoyalStraightFlush,
+    ];
+
+    /// JSONやCSVで使う役名を返します．serdeでの名前と同じです．
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::NoPair => "nopair",
+            Role::OnePair => "onepair",
+            Role::TwoPair => "twopair",
+            Role::ThreeCard => "threepair",
+            Role::Straight => "strait",
+            Role::Flush => "flush",
+            Role::FullHouse => "fulhouse",
+            Role::FourCard => "fourpair",
+            Role::StraightFlush => "straitflush",
+            Role::RoyalStraightFlush => "royalflush",
+        }
+    }
+
+    /// 表示用の日本語の役名を返します．
+    pub fn japanese_name(self) -> &'static str {
+        match self {
+            Role::NoPair => "ノーペア",
+            Role::OnePair => "ワンペア",
+            Role::TwoPair => "ツーペア",
+            Role::ThreeCard => "スリーカード",
+            Role::Straight => "ストレート",
+            Role::Flush => "フラッシュ",
+            Role::FullHouse => "フルハウス",
+            Role::FourCard => "フォーカード",
+            Role::StraightFlush => "ストレートフラッシュ",
+            Role::RoyalStraightFlush => "ロイヤルストレートフラッシュ",
+        }
+    }
+}
+
+/// Cardを読み込むときに受け付ける形です．
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CardInput {
+    Id(u32),
+    Name(String),
+    Object { id: u32 },
+}
+
+impl TryFrom<CardInput> for Card {
+    type Error = anyhow::Error;
+
+    fn try_from(input: CardInput) -> Result<Self, Self::Error> {
+        match input {
+            CardInput::Id(id) | CardInput::Object { id } => {
+                Card::from_id(id).ok_or_else(|| anyhow!("Error: Invalid card id {}", id))
+            }
+            CardInput::Name(name) => name.parse(),
+        }
+    }
+}
+
 type PorkerResult<T> = anyhow::Result<T>;
 
+/// 1回の実行で許可するループ回数の上限の初期値です．
+pub const MAX_LOOP_NUM: u32 = 1_000_000;
+
+/// 手札を1つずつ記録する場合に許可するループ回数の上限の初期値です．
+pub const MAX_TRACE_LOOP_NUM: u32 = 1_000;
+
+/// 1回の判定で受け付ける手札の数の上限の初期値です．
+pub const MAX_EVALUATE_HANDS: usize = 10_000;
+
+/// 1つのデッキ(useCards)に入れられるカードの数の上限の初期値です．
+pub const MAX_USE_CARDS: usize = 1_000;
+
+/// 実行回数などの上限です．指定しなかった項目はMAX_LOOP_NUMなどの定数になります．
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Limits {
+    /// 1回の実行で許可するループ回数の上限
+    pub max_loop_num: u32,
+    /// 手札を1つずつ記録する場合のループ回数の上限
+    pub max_trace_loop_num: u32,
+    /// 1回の判定で受け付ける手札の数の上限
+    pub max_evaluate_hands: usize,
+    /// 1回のバッチで受け付けるデッキの数の上限
+    pub max_batch_rows: usize,
+    /// 1つのデッキに入れられるカードの数の上限
+    pub max_use_cards: usize,
+}
+
+impl Limits {
+    pub const DEFAULT: Limits = Limits {
+        max_loop_num: MAX_LOOP_NUM,
+        max_trace_loop_num: MAX_TRACE_LOOP_NUM,
+        max_evaluate_hands: MAX_EVALUATE_HANDS,
+        max_batch_rows: batch::MAX_BATCH_ROWS,
+        max_use_cards: MAX_USE_CARDS,
+    };
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits::DEFAULT
+    }
+}
+
+static LIMITS: RwLock<Limits> = RwLock::new(Limits::DEFAULT);
+
+/// 現在の上限を返します．
+pub fn limits() -> Limits {
+    *LIMITS.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// 上限を変更します．サーバーの起動時に設定ファイルの値で1回だけ呼びます．
+pub fn set_limits(limits: Limits) {
+    *LIMITS.write().unwrap_or_else(|e| e.into_inner()) = limits;
+}
+
+
 impl Card {
     ///IDを渡すことで，スートとランクを計算し，Card型を生成します．
     pub fn new<T>(id: T) -> Self
@@ -59,6 +226,15 @@ impl Card {
         Self { id, suit, rank}
     }
 
+    /// IDが0~51の範囲にあるカードならSomeでCard型を返し，範囲外ならNoneを返します．
+    pub fn from_id(id: u32) -> Option<Self> {
+        if id < 52 {
+            Some(Card::new(id))
+        } else {
+            None
+        }
+    }
+
     /// デバッグ用に52枚すべてのカードidをもったベクタを返します．
     #[allow(unused)]
     pub fn all_cards_id() -> Vec<u32> {
@@ -74,6 +250,103 @@ impl Card {
 
 
 
+/// カードの一覧の文字列を，IDのベクタとして読み込みます．
+/// JSONの配列("[0, "Kd", ...]")か，カンマや空白で区切ったIDや表記("0,Kd 12 Ts")を受け付けます．
+pub fn parse_deck(text: &str) -> PorkerResult<Vec<u32>> {
+    if text.trim_start().starts_with('[') {
+        let cards: Vec<Card> = serde_json::from_str(text)?;
+        return Ok(cards.iter().map(|x| x.id).collect());
+    }
+
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|x| !x.is_empty())
+        .map(|x| match x.parse::<u32>() {
+            Ok(id) => Card::from_id(id).map(|card| card.id).ok_or_else(|| anyhow!("Error: Invalid card id {}", id)),
+            Err(_) => x.parse::<Card>().map(|card| card.id),
+        })
+        .collect()
+}
+
+/// ランクの表記です．1(A)から13(K)の順に並んでいます．
+const RANK_NAMES: [&str; 13] = ["A", "2", "3", "4", "5", "6", "7", "8", "9", "T", "J", "Q", "K"];
+
+/// s(スペード),h(ハート),d(ダイヤ),c(クラブ)の1文字で表示します．
+/// {:#}で表示すると，♠♥♦♣の記号で表示します．
+impl fmt::Display for Suit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (letter, symbol) = match self {
+            Suit::Spade => ('s', '♠'),
+            Suit::Heart => ('h', '♥'),
+            Suit::Diamond => ('d', '♦'),
+            Suit::Club => ('c', '♣'),
+        };
+        write!(f, "{}", if f.alternate() { symbol } else { letter })
+    }
+}
+
+/// s,h,d,cの1文字(大文字も可)，♠♥♦♣(白抜きの♤♡♢♧も可)，spade(s)などの英語名を読み込みます．
+impl std::str::FromStr for Suit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "s" | "♠" | "♤" | "spade" | "spades" => Ok(Suit::Spade),
+            "h" | "♥" | "♡" | "heart" | "hearts" => Ok(Suit::Heart),
+            "d" | "♦" | "♢" | "diamond" | "diamonds" => Ok(Suit::Diamond),
+            "c" | "♣" | "♧" | "club" | "clubs" => Ok(Suit::Club),
+            _ => Err(anyhow!("Error: Invalid suit {:?}", s)),
+        }
+    }
+}
+
+/// "As"(スペードのA)，"Td"(ダイヤの10)のような2文字の表記で表示します．
+/// ランクはA,2~9,T,J,Q,Kです．{:#}で表示すると，"A♠"のようにスートを記号で表示します．
+impl fmt::Display for Card {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", RANK_NAMES[(self.rank as usize + 12) % 13])?;
+        fmt::Display::fmt(&self.suit, f)
+    }
+}
+
+/// "As"，"Td"のような2文字の表記のほかに，"10♥"や"1s"のように数字のランクと記号のスートも読み込みます．
+/// ランクとスートの大文字小文字は区別しません．
+impl std::str::FromStr for Card {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        // スートは最後の1文字
+        let (rank_str, suit_str) = match s.char_indices().last() {
+            Some((i, _)) if i > 0 => s.split_at(i),
+            _ => return Err(anyhow!("Error: Invalid card {:?}", s)),
+        };
+
+        let rank = match rank_str.to_uppercase().as_str() {
+            "A" | "1" => 1,
+            "T" | "10" => 10,
+            "J" | "11" => 11,
+            "Q" | "12" => 12,
+            "K" | "13" => 13,
+            r => match r.parse::<u32>() {
+                Ok(n) if (2..=9).contains(&n) => n,
+                _ => return Err(anyhow!("Error: Invalid card {:?}", s)),
+            },
+        };
+        let suit: Suit = suit_str.parse().map_err(|_| anyhow!("Error: Invalid card {:?}", s))?;
+
+        Ok(Card::new(suit as u32 * 13 + rank - 1))
+    }
+}
+
+/// 1回分の手札の記録です．
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandTrace {
+    /// 配られた順の手札のID
+    pub cards: [u32; 5],
+    pub role: Role,
+    pub score: i64,
+}
+
 ///手札のカードのid配列を読み込んでCard型配列に変換します．
 pub fn make_cards_from_id(cards_id: &[u32; 5]) -> [Card; 5] {
     let mut cards = [Card {
@@ -90,14 +363,21 @@ pub fn make_cards_from_id(cards_id: &[u32; 5]) -> [Card; 5] {
 }
 
 /// 使用するカードのID一覧を持つベクタから，ランダムに選んだ5枚で手札ID配列を生成します
-pub fn handout_cards(use_cards: &Vec<u32>) -> PorkerResult<[u32; 5]> {
+pub fn handout_cards(use_cards: &[u32]) -> PorkerResult<[u32; 5]> {
+    handout_cards_with_rng(use_cards, &mut thread_rng())
+}
+
+/// handout_cardsと同じですが，使う乱数生成器を指定できます．
+/// シードを固定した乱数生成器を渡すと，同じ手札の列を再現できます．
+pub fn handout_cards_with_rng<R: Rng + ?Sized>(use_cards: &[u32], rng: &mut R) -> PorkerResult<[u32; 5]> {
     // 重複回避のためにハッシュマップを使用しています
 
     let mut handout_hash = FxHashMap::default();
-    let mut rng = thread_rng();
+    if use_cards.is_empty() {
+        return Err(anyhow!("Error: Invalid useCards"));
+    }
 
     // entryを使って重複をしないようにデータを挿入
-    // 重複しないカードが5枚未満の場合，エラーとなる
     let mut count = 0;
     while handout_hash.iter().len() < 5 {
         let len = handout_hash.len();
@@ -114,8 +394,10 @@ pub fn handout_cards(use_cards: &Vec<u32>) -> PorkerResult<[u32; 5]> {
 
         count += 1;
 
+        // 同じカードばかり引いて揃わない場合は，重複を除いたカードから選び直す
+        // 重複しないカードが5枚未満の場合，エラーとなる
         if count > 20 {
-            return Err(anyhow!("Error: Invalid useCards"));
+            return handout_distinct_cards(use_cards, rng);
         }
     }
 
@@ -129,192 +411,172 @@ pub fn handout_cards(use_cards: &Vec<u32>) -> PorkerResult<[u32; 5]> {
     Ok(handout_id)
 }
 
+/// 重複を除いたカードから5枚を選びます．
+fn handout_distinct_cards<R: Rng + ?Sized>(use_cards: &[u32], rng: &mut R) -> PorkerResult<[u32; 5]> {
+    let distinct = distinct_cards(use_cards);
+    if distinct.len() < 5 {
+        return Err(anyhow!("Error: Invalid useCards"));
+    }
 
-/// 同じランクのカードが何枚あるかを数え，その枚数に応じたRoleを返します．
-// 途中までResult型を返していましたが，設計上のミスだったためOption型を返すようになりました
-// 列挙型の使用に伴い大幅な仕様変更がありました. boolを返さず．データが意味を持つようになりました．
-pub fn is_pair(cards: &[Card; 5]) -> Option<Role> {
-    // 同じランクのカードが何枚あるかを数える．
-    let pair_count = (0..5)
-        .map(|i| cards.iter().filter(|x| x.rank == cards[i].rank).count())
-        .max()
-        .unwrap();
-    
-    // pair_countが2の場合はツーペアの可能性があるため，処理を分岐しています
-    match pair_count {
-        4 => Some(Role::FourCard),
-        3 => Some(Role::ThreeCard),
-        2 => {
-            if is_twopair(cards).is_some() {
-                Some(Role::TwoPair)
-            }else{
-                Some(Role::OnePair)
-            }
-        },
-        1 => Some(Role::NoPair),
-        _ => None, 
+    let mut handout_id: [u32; 5] = [0; 5];
+    for (slot, id) in handout_id.iter_mut().zip(distinct.choose_multiple(rng, 5)) {
+        *slot = *id;
     }
+    Ok(handout_id)
 }
 
-pub fn is_flush(cards: &[Card; 5]) -> Option<Role> {
-    // すべてのスートが同じかどうか
-    if cards.iter().all(|x| x.suit == cards[0].suit) {
-        Some(Role::Flush)
-    }else{
-        None
-    }
+/// 重複を除いたカードのIDを小さい順に返します．
+fn distinct_cards(use_cards: &[u32]) -> Vec<u32> {
+    let mut distinct = use_cards.to_vec();
+    distinct.sort_unstable();
+    distinct.dedup();
+    distinct
 }
 
-pub fn is_strait(cards: &mut [Card; 5]) -> Option<Role> {
-    // すべてのランクが連続しているかどうか
-    
-    // エースハイストレートの場合は，1, 10, 11, 12, 13となる．
-    const ACE_HIGH: [u32; 5] = [1, 10, 11, 12, 13];
 
-    let is_strait_1 = cards.iter().zip(ACE_HIGH.iter()).all(|(a, b)| a.rank == *b);
-    let is_strait_2 = (0..4).all(|i| cards[i].rank + 1 == cards[i + 1].rank);
+/// 役判定の結果です．役と，その役を作っているカードを保持します．
+/// 画面でカードを強調表示するときなどに使います．
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Classification {
+    pub role: Role,
+    /// 役を作っているカード(ランクの小さい順)．
+    /// ペア系の役ではペアになっているカード，ストレート・フラッシュ系とフルハウスでは5枚すべて，
+    /// ノーペアでは一番強いカード1枚です．
+    pub cards: Vec<Card>,
+}
 
-    if is_strait_1 || is_strait_2 {
-        Some(Role::Straight)
+/// エースを14として扱った，強さ比較用のランクを返します．
+pub fn rank_value(rank: u32) -> u32 {
+    if rank == 1 {
+        14
     } else {
-        None
+        rank
     }
 }
 
-pub fn is_royalflush(cards: &[Card; 5]) -> Option<Role> {
-    is_flush(cards)?;
+/// 手札の役を判定します．手札の並び順に関係なく，同じ手札なら同じ役を返します．
+pub fn classify(cards: &[Card; 5]) -> Role {
+    classify_with_reason(cards).role
+}
 
-    let mut is_royalflush = Some(Role::RoyalStraightFlush);
-    for i in cards.iter().take(5) {
-        if i.rank < 10 && i.rank > 1 {
-            is_royalflush = None;
-        }
-    }
+/// 手札の役と，その役を作っているカードを返します．
+/// 手札は内部でランクの順に並べ替えるので，呼び出し側で並べ替える必要はありません．
+pub fn classify_with_reason(cards: &[Card; 5]) -> Classification {
+    let mut sorted = *cards;
+    sorted.sort_unstable_by_key(|x| (x.rank, x.suit));
 
-    is_royalflush
-}
+    // エースハイストレートの場合は，1, 10, 11, 12, 13となる．
+    const ACE_HIGH: [u32; 5] = [1, 10, 11, 12, 13];
 
-pub fn is_straitflush(cards: &mut [Card; 5]) -> Option<Role> {
-    if is_strait(cards).is_some() && is_flush(cards).is_some() {
-        Some(Role::StraightFlush)
-    } else {
-        None
-    }
-}
+    let ranks: Vec<u32> = sorted.iter().map(|x| x.rank).collect();
+    let is_flush = sorted.iter().all(|x| x.suit == sorted[0].suit);
+    let is_ace_high = ranks == ACE_HIGH;
+    let is_strait = is_ace_high || (0..4).all(|i| ranks[i] + 1 == ranks[i + 1]);
+
+    // 同じランクのカードが何枚あるか
+    let rank_count = |rank: u32| ranks.iter().filter(|x| **x == rank).count();
+    let mut counts: Vec<usize> = ranks.iter().map(|x| rank_count(*x)).collect();
+    counts.sort_unstable_by(|a, b| b.cmp(a));
+    let pairs = counts.iter().filter(|x| **x == 2).count() / 2;
+
+    let role = match (is_strait, is_flush, counts[0]) {
+        (true, true, _) if is_ace_high => Role::RoyalStraightFlush,
+        (true, true, _) => Role::StraightFlush,
+        (_, _, 4..) => Role::FourCard,
+        (_, _, 3) if pairs == 1 => Role::FullHouse,
+        (_, true, _) => Role::Flush,
+        (true, _, _) => Role::Straight,
+        (_, _, 3) => Role::ThreeCard,
+        _ if pairs == 2 => Role::TwoPair,
+        _ if pairs == 1 => Role::OnePair,
+        _ => Role::NoPair,
+    };
+
+    let cards = match role {
+        Role::FourCard | Role::ThreeCard | Role::TwoPair | Role::OnePair => sorted
+            .iter()
+            .filter(|x| rank_count(x.rank) >= 2)
+            .copied()
+            .collect(),
+        Role::NoPair => sorted
+            .iter()
+            .max_by_key(|x| (rank_value(x.rank), x.suit))
+            .copied()
+            .into_iter()
+            .collect(),
+        _ => sorted.to_vec(),
+    };
 
-pub fn is_twopair(cards: &[Card; 5]) -> Option<Role> {
-    let mut count1 = false;
-    let mut counted = 999;
+    Classification { role, cards }
+}
 
-    'outer: for i in 0..5 {
-        counted = cards[i].rank;
+/// 同じ役同士の強さを比べるための値です．
+/// 役が同じ場合は，kickersを先頭から順に比べます．そのため，そのまま大小比較すると手札の強さの比較になります．
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HandRank {
+    pub role: Role,
+    /// 比べる順に並べたランク(エースは14)．
+    /// ペア系の役とフルハウスでは枚数の多い組から，それ以外では強いカードから並べます．
+    /// ストレート系では一番強いカードだけで，A,2,3,4,5のストレートは5になります．
+    pub kickers: Vec<u32>,
+}
 
-        for j in i + 1..5 {
-            if cards[i].rank == cards[j].rank {
-                count1 = true;
-                break 'outer;
-            }
+impl HandRank {
+    /// 大小関係がHandRankと同じになる1つの整数にまとめます．
+    /// 役を上位の桁に，kickersを1枚4ビットずつ並べた値です．
+    pub fn value(&self) -> u32 {
+        let mut value = self.role as u32;
+        for i in 0..5 {
+            value = (value << 4) | self.kickers.get(i).copied().unwrap_or(0);
         }
+        value
     }
+}
 
-    if !count1 {
-        return None;
-    }
+/// 手札の強さを，同じ役同士でも比べられる形で返します．
+pub fn rank_hand(cards: &[Card; 5]) -> HandRank {
+    let role = classify(cards);
 
-    let mut count2 = false;
-    'outer: for i in 0..5 {
-        if cards[i].rank == counted {
-            continue;
-        }
+    let mut values: Vec<u32> = cards.iter().map(|x| rank_value(x.rank)).collect();
+    values.sort_unstable_by(|a, b| b.cmp(a));
 
-        for j in i + 1..5 {
-            if cards[i].rank == cards[j].rank {
-                count2 = true;
-                break 'outer;
+    let kickers = match role {
+        Role::Straight | Role::StraightFlush | Role::RoyalStraightFlush => {
+            // A,2,3,4,5のストレートではエースを1として扱う
+            if values == [14, 5, 4, 3, 2] {
+                vec![5]
+            } else {
+                vec![values[0]]
             }
         }
-    }
-
-    if count1 && count2{
-        Some(Role::TwoPair)
-    }else {
-        None
-    }
-}
+        _ => {
+            // 枚数の多い組，同じ枚数なら強いランクの順に並べる
+            let mut groups: Vec<(usize, u32)> = Vec::new();
+            for value in values.iter() {
+                if !groups.iter().any(|x| x.1 == *value) {
+                    groups.push((values.iter().filter(|x| *x == value).count(), *value));
+                }
+            }
+            groups.sort_unstable_by(|a, b| b.cmp(a));
+            groups.iter().map(|x| x.1).collect()
+        }
+    };
 
-pub fn is_fulhouse(cards: &mut [Card; 5]) -> Option<Role> {
-    //  rankをキーにソートされているならば2パターンしかありません
-
-    let is_fulhouse_1 = 
-        //cards[0].rank == cards[0].rank&&
-        cards[1].rank == cards[0].rank
-        //&& cards[2].rank == cards[2].rank
-        && cards[3].rank == cards[2].rank
-        && cards[4].rank == cards[2].rank
-    ;
-
-    let is_fulhouse_2 = 
-        //cards[0].rank == cards[0].rank&&
-        cards[1].rank == cards[0].rank
-        && cards[2].rank == cards[0].rank
-        //&& cards[3].rank == cards[3].rank
-        && cards[4].rank == cards[3].rank
-    ;
-
-    if is_fulhouse_1 || is_fulhouse_2{
-        Some(Role::FullHouse)
-    }else{
-        None
-    }
+    HandRank { role, kickers }
 }
 
 /// 役判定を行います.
-pub fn count_judge_role(cards: &mut [Card; 5], role_count: &mut [u32; 10]){
-    // 事前にカード配列をソートしておく
-    // カード配列をrankをキーにソート． 安定ソートである必要はないため，unstable で不安定ソートを使うことにより高速化
-    cards.sort_unstable_by(|a, b| a.rank.cmp(&b.rank));
-
-    if is_royalflush(cards).is_some() {
-        role_count[9] += 1;
-    } else if is_straitflush(cards).is_some() {
-        role_count[8] += 1;
-    } else if is_strait(cards).is_some() {
-        role_count[7] += 1;
-    } else if is_flush(cards).is_some() {
-        role_count[6] += 1;
-    } else if is_fulhouse(cards).is_some() {
-        role_count[5] += 1;
-    } else {
-        match is_pair(cards){
-            Some(Role::FourCard) => role_count[4] += 1,
-            Some(Role::ThreeCard) => role_count[3] += 1,
-            Some(Role::TwoPair) => role_count[2] += 1,
-            Some(Role::OnePair) => role_count[1] += 1,
-            Some(Role::NoPair) => role_count[0] += 1,
-            _ => (),
-        }
-    } 
+/// 判定した役を返すので，続けてスコアの計算などに使えます．
+pub fn count_judge_role(cards: &[Card; 5], role_count: &mut RoleCounts) -> Role {
+    let role = classify(cards);
+    role_count.increment(role);
+    role
 }
 
 /// デバッグ用に，それぞれの役が出る確率を計算して表示します．
-pub fn debug_judge_role(role_count: &[u32; 10], total_num_of_atempt: u32) {
-    let roles = [
-        "ノーペア",
-        "ワンペア",
-        "ツーペア",
-        "スリーカード",
-        "フォーカード",
-        "フルハウス",
-        "フラッシュ",
-        "ストレート",
-        "ストレートフラッシュ",
-        "ロイヤルストレートフラッシュ",
-    ];
-    let mut rate = [0.; 10];
-
-    for i in 0..10 {
-        rate[i] = role_count[i] as f64 / total_num_of_atempt as f64;
-        println!("{:<20}: {:.5}%", roles[i], rate[i] * 100.);
+pub fn debug_judge_role(role_count: &RoleCounts) {
+    for (role, rate) in role_count.probabilities() {
+        println!("{:<20}: {:.5}%", role.japanese_name(), rate * 100.);
     }
     println!();
 }
@@ -322,7 +584,7 @@ pub fn debug_judge_role(role_count: &[u32; 10], total_num_of_atempt: u32) {
 /// 必要な処理がひとまとめになった関数です．
 /// 回数制限，手札選び，役判定，指定回数ループ，スコア計算
 /// 事実上，pubキーワードはこの関数にのみついていれば問題ありません．
-pub fn million_porker<T>(use_cards: &Vec<u32>, num: T) -> PorkerResult<([u32;10], u32, u32)>
+pub fn million_porker<T>(use_cards: &[u32], num: T, sheet: &ScoreSheet) -> PorkerResult<(RoleCounts, i64, u32)>
 where
     T: TryInto<u32>,
     <T as std::convert::TryInto<u32>>::Error: std::fmt::Debug,
@@ -334,50 +596,197 @@ where
         }
     }; 
 
-    //ループ回数が100万回を超えていたら，100万回まで減らす
-    let loop_num = if num > 1_000_000 {
-        1_000_000
-    } else {
-        num
-    };
+    let loop_num = limit_loop_num(num);
 
-    let mut role_count= [0; 10] ;
+    run_porker(use_cards, loop_num, sheet, loop_num, |_, _, _| true)
+}
+
+/// million_porkerから回数制限を外し，途中経過を受け取れるようにした関数です．
+/// progress_every回ごとに，それまでの役の出現回数とスコアの合計，実行済みの回数をon_progressに渡します．
+/// on_progressがfalseを返した場合は，そこで中断してエラーを返します．
+/// スコアは手札ごとにスコアシートで計算します．
+pub fn run_porker<F>(
+    use_cards: &[u32],
+    loop_num: u32,
+    sheet: &ScoreSheet,
+    progress_every: u32,
+    on_progress: F,
+) -> PorkerResult<(RoleCounts, i64, u32)>
+where
+    F: FnMut(&RoleCounts, i64, u32) -> bool,
+{
+    run_porker_with_hands(use_cards, loop_num, sheet, progress_every, on_progress, |_, _, _| {})
+}
 
-    for _ in 0..loop_num{
+/// run_porkerと同じですが，手札が配られるたびに，手札のID，役，スコアをon_handに渡します．
+/// 手札ごとの記録や，スコアの分布を集めるのに使います．
+pub fn run_porker_with_hands<F, H>(
+    use_cards: &[u32],
+    loop_num: u32,
+    sheet: &ScoreSheet,
+    progress_every: u32,
+    on_progress: F,
+    on_hand: H,
+) -> PorkerResult<(RoleCounts, i64, u32)>
+where
+    F: FnMut(&RoleCounts, i64, u32) -> bool,
+    H: FnMut(&[u32; 5], Role, i64),
+{
+    run_porker_with_rng(
+        use_cards,
+        loop_num,
+        sheet,
+        progress_every,
+        on_progress,
+        on_hand,
+        &mut thread_rng(),
+    )
+}
+
+/// run_porker_with_handsと同じですが，使う乱数生成器を指定できます．
+/// シードを固定した乱数生成器を渡すと，同じ結果を再現できます．
+pub fn run_porker_with_rng<F, H, R>(
+    use_cards: &[u32],
+    loop_num: u32,
+    sheet: &ScoreSheet,
+    progress_every: u32,
+    mut on_progress: F,
+    mut on_hand: H,
+    rng: &mut R,
+) -> PorkerResult<(RoleCounts, i64, u32)>
+where
+    F: FnMut(&RoleCounts, i64, u32) -> bool,
+    H: FnMut(&[u32; 5], Role, i64),
+    R: Rng + ?Sized,
+{
+    let progress_every = progress_every.max(1);
+    let mut role_count = RoleCounts::new();
+    let mut sum_score = 0;
+
+    for i in 1..=loop_num{
         //カードをランダムに5枚選び出す（idのみ）
-        let cards = handout_cards(use_cards)?;
+        let cards_id = handout_cards_with_rng(use_cards, rng)?;
         //idからCard型を生成する
-        let mut cards = make_cards_from_id(&cards);
+        let cards = make_cards_from_id(&cards_id);
         // 役判定を行う
-
-        count_judge_role(&mut cards, &mut role_count);
+        let role = count_judge_role(&cards, &mut role_count);
+        // 役の回数だけでなく，実際のカードを見てスコアを計算する
+        let score = sheet.score_hand(&cards, role);
+        sum_score += score;
+        on_hand(&cards_id, role, score);
+
+        if i % progress_every == 0 && i != loop_num && !on_progress(&role_count, sum_score, i) {
+            return Err(anyhow!("Error: Cancelled"));
+        }
     }
 
-    let sum_score = calc_score(&role_count);
-    
     Ok((role_count, sum_score, loop_num))
 }
 
-/// 総スコアを計算します．
-pub fn calc_score(role_count: &[u32;10]) -> u32 {
-    /*indexの小さい順に
-        ノーペア,
-        ワンペア,
-        ツーペア,
-        スリーカード,
-        フォーカード,
-        フルハウス,
-        フラッシュ,
-        ストレート,
-        ストレートフラッシュ,
-        ロイヤルストレートフラッシュ, 
-    */
-    const SCORE_SHEET:[u32;10] = [1, 5, 10, 20, 100, 150, 200, 500, 800, 1500];
-
-    let sum_score:u32 = role_count.iter()
-        .zip(SCORE_SHEET.iter())
-        .map(|x| x.0 * x.1)
-        .sum();
-
-    sum_score
+/// million_porkerと同じですが，配られた手札と役，スコアを1回ずつ記録して返します．
+/// 記録が大きくなりすぎないよう，回数がmax_trace_loop_numを超える場合はエラーになります．
+pub fn trace_porker(
+    use_cards: &[u32],
+    num: u32,
+    sheet: &ScoreSheet,
+) -> PorkerResult<(RoleCounts, i64, u32, Vec<HandTrace>)> {
+    let max = limits().max_trace_loop_num;
+    if num > max {
+        return Err(anyhow!("Error: trace is allowed only for num <= {}", max));
+    }
+
+    let mut traces = Vec::with_capacity(num as usize);
+    let (role_count, sum_score, loop_num) = run_porker_with_hands(
+        use_cards,
+        num,
+        sheet,
+        num,
+        |_, _, _| true,
+        |cards_id, role, score| {
+            traces.push(HandTrace {
+                cards: *cards_id,
+                role,
+                score,
+            })
+        },
+    )?;
+
+    Ok((role_count, sum_score, loop_num, traces))
+}
+
+/// 指定された手札1つの判定結果です．
+/// 手札のID，役と強さ，スコア，役を作っているカード
+#[derive(Debug, Clone)]
+pub struct Evaluation {
+    pub cards: [u32; 5],
+    pub rank: HandRank,
+    pub score: i64,
+    pub role_cards: Vec<Card>,
+}
+
+/// 指定された手札をそれぞれ判定し，スコアシートでスコアを計算します．
+/// 手札が5枚でない場合や，同じカードが2回入っている場合はエラーになります．
+pub fn evaluate_hands(hands: &[Vec<u32>], sheet: &ScoreSheet) -> PorkerResult<Vec<Evaluation>> {
+    let max = limits().max_evaluate_hands;
+    if hands.len() > max {
+        return Err(anyhow!("Error: evaluate is allowed only for {} hands or less", max));
+    }
+
+    hands
+        .iter()
+        .enumerate()
+        .map(|(i, hand)| {
+            let cards_id: [u32; 5] = hand
+                .as_slice()
+                .try_into()
+                .map_err(|_| anyhow!("Error: hand {} must have 5 cards", i))?;
+            if (1..5).any(|j| cards_id[..j].contains(&cards_id[j])) {
+                return Err(anyhow!("Error: hand {} has duplicate cards", i));
+            }
+
+            let cards = make_cards_from_id(&cards_id);
+            let rank = rank_hand(&cards);
+            Ok(Evaluation {
+                cards: cards_id,
+                score: sheet.score_hand(&cards, rank.role),
+                role_cards: classify_with_reason(&cards).cards,
+                rank,
+            })
+        })
+        .collect()
+}
+
+/// デッキから手札を配れるか確かめます．重複しないカードが5枚未満ならエラーです．
+pub fn check_deck(use_cards: &[u32]) -> PorkerResult<()> {
+    if distinct_cards(use_cards).len() < 5 {
+        return Err(anyhow!("Error: useCards must have at least 5 distinct cards"));
+    }
+    Ok(())
+}
+
+/// シードが指定されていればそれを，無ければランダムなシードを返します．
+/// JavaScriptの数値で正確に扱えるよう，自動で決めるシードは2^53未満にします．
+pub fn resolve_seed(seed: Option<u64>) -> u64 {
+    seed.unwrap_or_else(|| thread_rng().gen_range(0..1 << 53))
+}
+
+/// ループ回数が上限(省略時は100万回)を超えていたら，上限まで減らします．
+pub fn limit_loop_num(num: u32) -> u32 {
+    num.min(limits().max_loop_num)
+}
+
+/// 1つの役のスコアを返します．
+pub fn role_score(role: Role) -> u32 {
+    match role {
+        Role::NoPair => 1,
+        Role::OnePair => 5,
+        Role::TwoPair => 10,
+        Role::ThreeCard => 20,
+        Role::Straight => 500,
+        Role::Flush => 200,
+        Role::FullHouse => 150,
+        Role::FourCard => 100,
+        Role::StraightFlush => 800,
+        Role::RoyalStraightFlush => 1500,
+    }
 }
@@ -0,0 +1,178 @@
+//! カード1枚ごとの貢献度を分析するモジュールです．
+//! 手札は使うカードから一様に5枚選ばれるので，あるカードを含まない手札だけを集めると，
+//! そのカードを抜いたデッキで実行した結果と同じ分布になります．
+//! これを利用して，1回のシミュレーションで全カードの「抜いたときのスコアの変化」を計算しています．
+//! 同じように，あるカードを別のカードに入れ替えたデッキの手札は，元の手札のそのカードを入れ替えたものと同じ分布になるので，
+//! 配った手札のカードを入れ替えて採点し直すことで「入れ替えたときのスコアの変化」も計算できます．
+//! 重複のあるデッキでは，どちらも近似になります．
+
+use super::*;
+
+/// 入れ替えの分析をする場合の回数の上限です．手札1つごとに，デッキに無いカードの数×5回採点し直すためです．
+pub const MAX_SWAP_LOOP_NUM: u32 = 100_000;
+
+/// カードを別のカードに入れ替えたときの，1回あたりの期待スコアの変化です．
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SwapEffect {
+    /// 入れ替え先のカード
+    pub id: u32,
+    pub delta: f64,
+}
+
+/// カード1枚ごとの分析結果を保持する構造体です．
+#[derive(Debug, Clone, PartialEq)]
+pub struct CardContribution {
+    pub id: u32,
+    /// このカードが手札に入った回数
+    pub hands: u32,
+    /// このカードが入った手札での，それぞれの役の出現回数
+    pub role_count: RoleCounts,
+    /// このカードが入った手札の平均スコア
+    pub mean_score: f64,
+    /// このカードを抜いたときの，1回あたりの期待スコアの変化
+    /// 抜くと手札が作れなくなる場合などはNoneになります
+    pub removed_delta: Option<f64>,
+    /// デッキに無いカードと入れ替えたときの変化．変化の大きい順に並んでいます
+    /// 入れ替えを分析しなかった場合はNoneになります
+    pub swaps: Option<Vec<SwapEffect>>,
+}
+
+/// 分析全体の結果を保持する構造体です．
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    pub loop_num: u32,
+    pub role_count: RoleCounts,
+    pub sum_score: i64,
+    /// 1回あたりの平均スコア
+    pub mean_score: f64,
+    /// IDの小さい順に並んだカードごとの結果
+    pub cards: Vec<CardContribution>,
+}
+
+/// カードごとの途中経過です．
+#[derive(Default)]
+struct Tally {
+    role_count: RoleCounts,
+    score: i64,
+    /// 入れ替え先のカードごとの，入れ替えたときのスコアの差の合計
+    swap_score: Vec<i64>,
+}
+
+/// 使うカードから指定回数手札を選び，カード1枚ごとの役の出現回数と，抜いたときのスコアの変化を計算します．
+/// swapを指定すると，デッキに無いカードと入れ替えたときのスコアの変化も計算します．この場合の回数の上限はMAX_SWAP_LOOP_NUMです．
+/// スコアはスコアシートで計算します．回数の上限はmillion_porkerと同じです．
+pub fn analyze_cards(use_cards: &[u32], num: u32, sheet: &ScoreSheet, swap: bool) -> PorkerResult<Analysis> {
+    analyze_cards_with_rng(use_cards, num, sheet, swap, &mut thread_rng())
+}
+
+/// analyze_cardsと同じですが，渡した乱数生成器で手札を選びます．
+pub fn analyze_cards_with_rng<R: Rng + ?Sized>(
+    use_cards: &[u32],
+    num: u32,
+    sheet: &ScoreSheet,
+    swap: bool,
+    rng: &mut R,
+) -> PorkerResult<Analysis> {
+    let loop_num = if swap {
+        limit_loop_num(num).min(MAX_SWAP_LOOP_NUM)
+    } else {
+        limit_loop_num(num)
+    };
+
+    // 入れ替え先の候補は，デッキに無いカードすべて
+    let candidates: Vec<u32> = if swap {
+        (0..52).filter(|id| !use_cards.contains(id)).collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut tallies: FxHashMap<u32, Tally> = FxHashMap::default();
+    for id in use_cards {
+        tallies.entry(*id).or_insert_with(|| Tally {
+            swap_score: vec![0; candidates.len()],
+            ..Default::default()
+        });
+    }
+
+    let mut role_count = RoleCounts::new();
+    let mut total_score: i64 = 0;
+
+    for _ in 0..loop_num {
+        let cards_id = handout_cards_with_rng(use_cards, rng)?;
+        let cards = make_cards_from_id(&cards_id);
+        let role = classify(&cards);
+        let score = sheet.score_hand(&cards, role);
+
+        role_count.increment(role);
+        total_score += score;
+
+        for (i, id) in cards_id.iter().enumerate() {
+            let tally = tallies.get_mut(id).unwrap();
+            tally.role_count.increment(role);
+            tally.score += score;
+
+            for (j, candidate) in candidates.iter().enumerate() {
+                let mut swapped = cards;
+                swapped[i] = Card::new(*candidate);
+                tally.swap_score[j] += sheet.score_hand(&swapped, classify(&swapped)) - score;
+            }
+        }
+    }
+
+    let mean_score = mean(total_score, loop_num as u64);
+    let distinct_cards = tallies.len();
+
+    let mut cards: Vec<CardContribution> = tallies
+        .into_iter()
+        .map(|(id, tally)| {
+            // このカードを含まない手札だけの平均が，抜いたデッキでの期待スコアの推定値になる
+            let hands = tally.role_count.total();
+            let hands_without = loop_num as u64 - hands;
+            let removed_delta = if hands_without > 0 && distinct_cards > 5 {
+                Some(mean(total_score - tally.score, hands_without) - mean_score)
+            } else {
+                None
+            };
+
+            // このカードを含まない手札はスコアが変わらないので，差の合計を全体の回数で割る
+            let swaps = swap.then(|| {
+                let mut swaps: Vec<SwapEffect> = candidates
+                    .iter()
+                    .zip(tally.swap_score.iter())
+                    .map(|(id, sum)| SwapEffect {
+                        id: *id,
+                        delta: mean(*sum, loop_num as u64),
+                    })
+                    .collect();
+                swaps.sort_by(|a, b| b.delta.total_cmp(&a.delta).then(a.id.cmp(&b.id)));
+                swaps
+            });
+
+            CardContribution {
+                id,
+                hands: hands as u32,
+                role_count: tally.role_count,
+                mean_score: mean(tally.score, hands),
+                removed_delta,
+                swaps,
+            }
+        })
+        .collect();
+    cards.sort_unstable_by_key(|x| x.id);
+
+    Ok(Analysis {
+        loop_num,
+        role_count,
+        sum_score: total_score,
+        mean_score,
+        cards,
+    })
+}
+
+fn mean(sum: i64, count: u64) -> f64 {
+    if count == 0 {
+        0.
+    } else {
+        sum as f64 / count as f64
+    }
+}
@@ -0,0 +1,340 @@
+//! たくさんのデッキをまとめてシミュレーションするモジュールです．
+//! JSONL(1行に1つのJSON)かCSVでデッキと行ごとの設定を受け取り，CPUのスレッド数だけ並列に実行して，
+//! デッキごとに役の出現回数，スコア，1回あたりの平均スコアの信頼区間を1行ずつ返します．
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use super::constraints::DeckConstraints;
+use super::stats::{normal_quantile, Moments};
+use super::*;
+
+/// 1回のバッチで受け付けるデッキの数の上限の初期値です．
+pub const MAX_BATCH_ROWS: usize = 1000;
+
+/// 行で回数を指定しなかったときの回数です．
+pub const DEFAULT_BATCH_NUM: u32 = 100_000;
+
+/// 行で信頼度を指定しなかったときの値です．
+pub const DEFAULT_BATCH_CONFIDENCE: f64 = 0.95;
+
+/// 実行中にパニックした行のエラーです．
+const PANIC_ERROR: &str = "Error: simulation failed unexpectedly";
+
+/// 入出力の形式です．
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchFormat {
+    Jsonl,
+    Csv,
+}
+
+impl BatchFormat {
+    /// 最初の文字が{ならJSONL，それ以外はCSVとみなします．
+    pub fn detect(text: &str) -> BatchFormat {
+        if text.trim_start().starts_with('{') {
+            BatchFormat::Jsonl
+        } else {
+            BatchFormat::Csv
+        }
+    }
+}
+
+/// デッキ1つ分の設定です．
+/// JSONLでは{"id": "deck1", "useCards": ["As", 1, ...], "num": 100000, "seed": 1, "confidence": 0.95}の形で，
+/// useCards以外は省略できます．CSVでは見出しの行にこの名前を書き，useCardsはカンマか空白で区切ります．
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[allow(non_snake_case)]
+pub struct BatchRow {
+    pub id: Option<String>,
+    #[serde(deserialize_with = "deserialize_ids")]
+    pub useCards: Vec<u32>,
+    pub num: Option<u32>,
+    pub seed: Option<u64>,
+    pub confidence: Option<f64>,
+}
+
+fn deserialize_ids<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<u32>, D::Error> {
+    Ok(Vec::<Card>::deserialize(deserializer)?.iter().map(|x| x.id).collect())
+}
+
+/// デッキ1つ分の結果です．lineは入力の行番号(1始まり)です．
+/// 読み込みや実行に失敗した行は，errorだけが入ります．
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[allow(non_snake_case)]
+pub struct BatchResult {
+    pub line: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub number: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allscore: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meanScore: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stdError: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ciLow: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ciHigh: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<RoleCounts>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BatchResult {
+    fn failed(line: usize, id: Option<String>, error: String) -> BatchResult {
+        BatchResult {
+            line,
+            id,
+            number: None,
+            seed: None,
+            allscore: None,
+            meanScore: None,
+            stdError: None,
+            confidence: None,
+            ciLow: None,
+            ciHigh: None,
+            result: None,
+            error: Some(error),
+        }
+    }
+}
+
+/// 入力を行ごとに読み込みます．空行は飛ばし，読み込めなかった行はその行のエラーにします．
+pub fn parse_batch(text: &str, format: BatchFormat) -> PorkerResult<Vec<(usize, PorkerResult<BatchRow>)>> {
+    let lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|(_, line)| !line.trim().is_empty());
+
+    let rows: Vec<(usize, PorkerResult<BatchRow>)> = match format {
+        BatchFormat::Jsonl => lines
+            .map(|(line, text)| (line, serde_json::from_str(text).map_err(|e| anyhow!("Error: {}", e))))
+            .collect(),
+        BatchFormat::Csv => {
+            let mut lines = lines;
+            let header = match lines.next() {
+                Some((_, header)) => split_csv_line(header),
+                None => return Ok(Vec::new()),
+            };
+            lines
+                .map(|(line, text)| (line, csv_row(&header, &split_csv_line(text))))
+                .collect()
+        }
+    };
+
+    let max = limits().max_batch_rows;
+    if rows.len() > max {
+        return Err(anyhow!("Error: batch must have at most {} decks", max));
+    }
+    Ok(rows)
+}
+
+/// CSVの1行をセルに分けます．"で囲んだセルの中ではカンマを使えて，""は"1つになります．
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                cell.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => cells.push(std::mem::take(&mut cell)),
+            c => cell.push(c),
+        }
+    }
+    cells.push(cell);
+    cells.iter().map(|x| x.trim().to_string()).collect()
+}
+
+/// CSVの見出しとセルから，デッキ1つ分の設定を作ります．空のセルは省略したことになります．
+fn csv_row(header: &[String], cells: &[String]) -> PorkerResult<BatchRow> {
+    let cell = |name: &str| {
+        header
+            .iter()
+            .position(|x| x == name)
+            .and_then(|i| cells.get(i))
+            .filter(|x| !x.is_empty())
+    };
+
+    Ok(BatchRow {
+        id: cell("id").cloned(),
+        useCards: parse_deck(cell("useCards").ok_or_else(|| anyhow!("Error: useCards is required"))?)?,
+        num: parse_cell("num", cell("num"))?,
+        seed: parse_cell("seed", cell("seed"))?,
+        confidence: parse_cell("confidence", cell("confidence"))?,
+    })
+}
+
+/// 数値のセルを読み込みます．
+fn parse_cell<T: std::str::FromStr>(name: &str, cell: Option<&String>) -> PorkerResult<Option<T>> {
+    cell.map(|x| x.parse().map_err(|_| anyhow!("Error: {} must be a number (got {:?})", name, x)))
+        .transpose()
+}
+
+/// デッキ1つを実行します．
+fn run_row(line: usize, row: &BatchRow, sheet: &ScoreSheet, constraints: &DeckConstraints) -> PorkerResult<BatchResult> {
+    let max_use_cards = limits().max_use_cards;
+    if row.useCards.len() > max_use_cards {
+        return Err(anyhow!("Error: useCards must have at most {} cards", max_use_cards));
+    }
+    check_deck(&row.useCards)?;
+    constraints.validate(&row.useCards)?;
+    let confidence = row.confidence.unwrap_or(DEFAULT_BATCH_CONFIDENCE);
+    if !(confidence > 0. && confidence < 1.) {
+        return Err(anyhow!("Error: confidence must be between 0 and 1"));
+    }
+
+    let loop_num = limit_loop_num(row.num.unwrap_or(DEFAULT_BATCH_NUM));
+    let seed = resolve_seed(row.seed);
+    let mut moments = Moments::default();
+    let (role_count, sum_score, loop_num) = run_porker_with_rng(
+        &row.useCards,
+        loop_num,
+        sheet,
+        loop_num,
+        |_, _, _| true,
+        |_, _, score| moments.push(score as f64),
+        &mut StdRng::seed_from_u64(seed),
+    )?;
+
+    let half_width = normal_quantile(0.5 + confidence / 2.) * moments.std_error();
+    Ok(BatchResult {
+        line,
+        id: row.id.clone(),
+        number: Some(loop_num),
+        seed: Some(seed),
+        allscore: Some(sum_score),
+        meanScore: Some(moments.mean()),
+        stdError: Some(moments.std_error()),
+        confidence: Some(confidence),
+        ciLow: Some(moments.mean() - half_width),
+        ciHigh: Some(moments.mean() + half_width),
+        result: Some(role_count),
+        error: None,
+    })
+}
+
+/// 読み込んだ行をすべて実行し，入力と同じ順に結果を返します．
+/// workers個のスレッドで，空いたスレッドが次の行を取って実行します．
+/// 実行中にパニックした行はその行のエラーにし，他の行はそのまま実行します．
+pub fn run_batch(
+    rows: &[(usize, PorkerResult<BatchRow>)],
+    sheet: &ScoreSheet,
+    constraints: &DeckConstraints,
+    workers: usize,
+) -> Vec<BatchResult> {
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<Option<BatchResult>> = vec![None; rows.len()];
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers.max(1).min(rows.len().max(1)))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some((line, row)) = rows.get(i) else {
+                            break;
+                        };
+                        let result = match row {
+                            Ok(row) => {
+                                let run = panic::catch_unwind(AssertUnwindSafe(|| run_row(*line, row, sheet, constraints)));
+                                match run {
+                                    Ok(Ok(result)) => result,
+                                    Ok(Err(e)) => BatchResult::failed(*line, row.id.clone(), e.to_string()),
+                                    Err(_) => BatchResult::failed(*line, row.id.clone(), PANIC_ERROR.to_string()),
+                                }
+                            }
+                            Err(e) => BatchResult::failed(*line, None, e.to_string()),
+                        };
+                        done.push((i, result));
+                    }
+                    done
+                })
+            })
+            .collect();
+
+        // スレッドごと失敗した場合，その結果は下で行ごとのエラーにする
+        for done in handles.into_iter().filter_map(|handle| handle.join().ok()) {
+            for (i, result) in done {
+                results[i] = Some(result);
+            }
+        }
+    });
+
+    results
+        .into_iter()
+        .zip(rows)
+        .map(|(result, (line, row))| {
+            result.unwrap_or_else(|| {
+                let id = row.as_ref().ok().and_then(|row| row.id.clone());
+                BatchResult::failed(*line, id, PANIC_ERROR.to_string())
+            })
+        })
+        .collect()
+}
+
+/// 結果を指定した形式で書き出します．CSVでは役ごとの回数を列にします．
+pub fn write_batch(results: &[BatchResult], format: BatchFormat) -> String {
+    match format {
+        BatchFormat::Jsonl => results
+            .iter()
+            .map(|x| serde_json::to_string(x).unwrap_or_default() + "\n")
+            .collect(),
+        BatchFormat::Csv => {
+            let roles: Vec<&str> = Role::ALL.iter().map(|role| role.name()).collect();
+            let mut out = format!(
+                "line,id,number,seed,allscore,meanScore,stdError,confidence,ciLow,ciHigh,{},error\n",
+                roles.join(",")
+            );
+            for x in results {
+                let opt = |v: Option<String>| v.unwrap_or_default();
+                let mut cells = vec![
+                    x.line.to_string(),
+                    csv_cell(x.id.as_deref().unwrap_or_default()),
+                    opt(x.number.map(|v| v.to_string())),
+                    opt(x.seed.map(|v| v.to_string())),
+                    opt(x.allscore.map(|v| v.to_string())),
+                    opt(x.meanScore.map(|v| v.to_string())),
+                    opt(x.stdError.map(|v| v.to_string())),
+                    opt(x.confidence.map(|v| v.to_string())),
+                    opt(x.ciLow.map(|v| v.to_string())),
+                    opt(x.ciHigh.map(|v| v.to_string())),
+                ];
+                cells.extend(
+                    Role::ALL
+                        .iter()
+                        .map(|role| opt(x.result.map(|count| count[*role].to_string()))),
+                );
+                cells.push(csv_cell(x.error.as_deref().unwrap_or_default()));
+                out += &(cells.join(",") + "\n");
+            }
+            out
+        }
+    }
+}
+
+/// カンマや"，改行を含む値を"で囲みます．
+fn csv_cell(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
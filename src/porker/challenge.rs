@@ -0,0 +1,205 @@
+//! デイリーチャレンジのモジュールです．
+//! 日付ごとに決まったシードとルールがあり，同じ日のチャレンジでは全員が同じ乱数列で採点されるので，
+//! 提出したデッキの結果をそのまま比べられます．
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+use super::constraints::DeckConstraints;
+use super::scoring::ScoreRule;
+use super::*;
+
+/// 1回のチャレンジで配る手札の数です．
+pub const CHALLENGE_LOOP_NUM: u32 = 10_000;
+
+/// ルールで指定するデッキの枚数の候補です．
+const DECK_SIZES: [usize; 4] = [10, 15, 20, 25];
+
+/// ボーナスになったスートのスコアの倍率です．
+const BONUS_MULTIPLIER: i64 = 2;
+
+/// チャレンジの日付(UTC)です．JSONやURLでは"2023-03-12"の形式になります．
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    /// 今日の日付(UTC)を返します．
+    pub fn today() -> Date {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+        Date::from_days((secs / 86_400) as i64)
+    }
+
+    /// 1970-01-01からの日数を日付に変換します．
+    fn from_days(days: i64) -> Date {
+        // http://howardhinnant.github.io/date_algorithms.html の civil_from_days
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = (yoe + era * 400 + (month <= 2) as i64) as i32;
+        Date { year, month, day }
+    }
+
+    /// 1970-01-01からの日数を返します．
+    fn days(&self) -> i64 {
+        // http://howardhinnant.github.io/date_algorithms.html の days_from_civil
+        let year = self.year as i64 - (self.month <= 2) as i64;
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let mp = (self.month as i64 + 9) % 12;
+        let doy = (153 * mp + 2) / 5 + self.day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146_097 + doe - 719_468
+    }
+
+    /// その日のチャレンジのシードを返します．日付が同じなら，いつ誰が計算しても同じ値です．
+    pub fn seed(&self) -> u64 {
+        // splitmix64で日数をかき混ぜる．JavaScriptで正確に扱えるよう2^53未満にする
+        let mut x = (self.days() as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        (x ^ (x >> 31)) >> 11
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+impl std::str::FromStr for Date {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> PorkerResult<Date> {
+        let invalid = || anyhow!("Error: Invalid date {:?} (use YYYY-MM-DD)", s);
+        let parts: Vec<&str> = s.split('-').collect();
+        if parts.len() != 3 {
+            return Err(invalid());
+        }
+        let date = Date {
+            year: parts[0].parse().map_err(|_| invalid())?,
+            month: parts[1].parse().map_err(|_| invalid())?,
+            day: parts[2].parse().map_err(|_| invalid())?,
+        };
+        // 2月30日のような存在しない日付は，日数に直して戻すと別の日付になる
+        if !(1..=12).contains(&date.month) || Date::from_days(date.days()) != date {
+            return Err(invalid());
+        }
+        Ok(date)
+    }
+}
+
+/// チャレンジのルールです．
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rules {
+    /// デッキの制約．枚数は日付ごとに決まった枚数ちょうどで，同じカードは使えません
+    pub constraints: DeckConstraints,
+    /// 役を作っているカードがすべてこのスートの場合，スコアがbonus_multiplier倍になります
+    pub bonus_suit: Suit,
+    pub bonus_multiplier: i64,
+}
+
+impl Rules {
+    /// ボーナスのルールを，スコアシートとして返します．
+    pub fn score_sheet(&self) -> ScoreSheet {
+        ScoreSheet {
+            rules: vec![ScoreRule::SuitMultiplier {
+                role: None,
+                suit: self.bonus_suit,
+                multiplier: self.bonus_multiplier,
+            }],
+            ..Default::default()
+        }
+    }
+}
+
+/// その日のチャレンジです．
+#[derive(Debug, Clone, PartialEq)]
+pub struct Challenge {
+    pub date: Date,
+    pub seed: u64,
+    pub rules: Rules,
+}
+
+/// 1つのデッキを採点した結果です．
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChallengeResult {
+    pub role_count: RoleCounts,
+    pub score: i64,
+}
+
+impl Challenge {
+    /// 日付からチャレンジを作ります．ルールもシードから決めるので，同じ日付なら同じルールになります．
+    pub fn new(date: Date) -> Challenge {
+        let seed = date.seed();
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let deck_size = DECK_SIZES[rng.gen_range(0..DECK_SIZES.len())];
+        // 半分くらいの日は，1つのランクを使えなくする
+        let banned_ranks = if rng.gen_bool(0.5) {
+            vec![rng.gen_range(1..=13)]
+        } else {
+            Vec::new()
+        };
+        let bonus_suit = num::FromPrimitive::from_u32(rng.gen_range(0..4)).unwrap();
+
+        Challenge {
+            date,
+            seed,
+            rules: Rules {
+                constraints: DeckConstraints {
+                    min_size: Some(deck_size),
+                    max_size: Some(deck_size),
+                    distinct: true,
+                    banned_ranks,
+                    ..Default::default()
+                },
+                bonus_suit,
+                bonus_multiplier: BONUS_MULTIPLIER,
+            },
+        }
+    }
+
+    /// デッキを採点します．
+    /// 毎回その回のシードから乱数生成器を作り，並べ替えたデッキから手札を選ぶので，
+    /// 全員が同じ位置のカードを配られます．
+    pub fn score(&self, use_cards: &[u32]) -> PorkerResult<ChallengeResult> {
+        self.rules.constraints.validate(use_cards)?;
+
+        let mut deck = use_cards.to_vec();
+        deck.sort_unstable();
+
+        let sheet = self.rules.score_sheet();
+        let mut master_rng = StdRng::seed_from_u64(self.seed);
+        let mut role_count = RoleCounts::new();
+        let mut score = 0;
+
+        for _ in 0..CHALLENGE_LOOP_NUM {
+            let hand_seed: u64 = master_rng.gen();
+            let mut cards_id = [0; 5];
+            for (i, id) in deck
+                .choose_multiple(&mut StdRng::seed_from_u64(hand_seed), 5)
+                .enumerate()
+            {
+                cards_id[i] = *id;
+            }
+            let cards = make_cards_from_id(&cards_id);
+            let role = count_judge_role(&cards, &mut role_count);
+            score += sheet.score_hand(&cards, role);
+        }
+
+        Ok(ChallengeResult { role_count, score })
+    }
+}
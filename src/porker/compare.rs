@@ -0,0 +1,139 @@
+//! 複数のデッキを同じ乱数列で実行し，結果を比べるモジュールです．
+//! 1回ごとに共通のシードから乱数生成器を作り直して手札を選ぶので(共通乱数法)，
+//! 同じ位置に同じカードがあるデッキ同士では同じ手札が配られ，ばらつきの少ない比較ができます．
+
+use std::collections::BTreeMap;
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use super::stats::{two_sided_p_value, Moments};
+use super::*;
+
+/// 一度に比較できるデッキ数の上限です．
+pub const MAX_COMPARE_DECKS: usize = 10;
+
+/// 有意とみなす有意水準です．
+pub const SIGNIFICANCE_LEVEL: f64 = 0.05;
+
+/// デッキ1つ分の実行結果です．
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeckResult {
+    pub role_count: RoleCounts,
+    pub sum_score: i64,
+    pub mean_score: f64,
+}
+
+/// 基準のデッキ(1つ目)との差と，その検定結果です．
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeckDiff {
+    /// 比べたデッキの番号
+    pub deck: usize,
+    /// それぞれの役の出現回数の差
+    pub role_diff: BTreeMap<Role, i64>,
+    /// 総スコアの差
+    pub score_delta: i64,
+    /// 1回あたりのスコアの差の平均
+    pub mean_delta: f64,
+    /// mean_deltaの標準誤差
+    pub std_error: f64,
+    /// 対応のある差の検定のz値．差がまったくばらつかない場合はNone
+    pub z_score: Option<f64>,
+    pub p_value: f64,
+    pub significant: bool,
+}
+
+/// 比較全体の結果です．
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub loop_num: u32,
+    pub seed: u64,
+    pub decks: Vec<DeckResult>,
+    /// 2つ目以降のデッキについて，1つ目のデッキとの差
+    pub diffs: Vec<DeckDiff>,
+}
+
+/// 複数のデッキを同じ回数，同じ乱数列で実行して比較します．
+/// スコアはスコアシートで計算します．シードを省略した場合はランダムに決め，結果に含めて返します．
+pub fn compare_decks(decks: &[Vec<u32>], num: u32, seed: Option<u64>, sheet: &ScoreSheet) -> PorkerResult<Comparison> {
+    if decks.len() < 2 {
+        return Err(anyhow!("Error: At least 2 decks are required"));
+    }
+    if decks.len() > MAX_COMPARE_DECKS {
+        return Err(anyhow!(
+            "Error: Too many decks (max {})",
+            MAX_COMPARE_DECKS
+        ));
+    }
+
+    let loop_num = limit_loop_num(num);
+    let seed = resolve_seed(seed);
+    let mut master_rng = StdRng::seed_from_u64(seed);
+
+    let mut role_counts = vec![RoleCounts::new(); decks.len()];
+    let mut moments = vec![Moments::default(); decks.len()];
+    let mut diff_moments = vec![Moments::default(); decks.len()];
+    let mut scores = vec![0; decks.len()];
+    let mut sum_scores = vec![0; decks.len()];
+
+    for _ in 0..loop_num {
+        // この回で全デッキが共通して使うシード
+        let hand_seed: u64 = master_rng.gen();
+
+        for (i, deck) in decks.iter().enumerate() {
+            let mut rng = StdRng::seed_from_u64(hand_seed);
+            let cards = handout_cards_with_rng(deck, &mut rng)?;
+            let cards = make_cards_from_id(&cards);
+            let role = classify(&cards);
+
+            role_counts[i].increment(role);
+            scores[i] = sheet.score_hand(&cards, role);
+            sum_scores[i] += scores[i];
+            moments[i].push(scores[i] as f64);
+        }
+
+        for i in 1..decks.len() {
+            diff_moments[i].push((scores[i] - scores[0]) as f64);
+        }
+    }
+
+    let results: Vec<DeckResult> = (0..decks.len())
+        .map(|i| DeckResult {
+            role_count: role_counts[i],
+            sum_score: sum_scores[i],
+            mean_score: moments[i].mean(),
+        })
+        .collect();
+
+    let diffs = (1..decks.len())
+        .map(|i| {
+            let mean_delta = diff_moments[i].mean();
+            let std_error = diff_moments[i].std_error();
+            let (z_score, p_value) = if std_error > 0. {
+                let z = mean_delta / std_error;
+                (Some(z), two_sided_p_value(z))
+            } else if mean_delta == 0. {
+                (None, 1.)
+            } else {
+                (None, 0.)
+            };
+
+            DeckDiff {
+                deck: i,
+                role_diff: role_counts[i].diff(&role_counts[0]),
+                score_delta: results[i].sum_score - results[0].sum_score,
+                mean_delta,
+                std_error,
+                z_score,
+                p_value,
+                significant: p_value < SIGNIFICANCE_LEVEL,
+            }
+        })
+        .collect();
+
+    Ok(Comparison {
+        loop_num,
+        seed,
+        decks: results,
+        diffs,
+    })
+}
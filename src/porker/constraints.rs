@@ -0,0 +1,227 @@
+//! デッキ(useCards)の制約を扱うモジュールです．
+//! 枚数，スートごとやランクごとの上限，使えないカード，コストの予算などを宣言的に書いておき，
+//! シミュレーションの前にまとめて確認します．破っているルールはすべて返します．
+
+use std::collections::BTreeMap;
+
+use super::*;
+
+/// デッキの制約です．指定しなかった項目は確認しません．
+/// JSONでは{"minSize": 10, "maxPerSuit": 5, "bannedCards": ["As"], "budget": 30, ...}の形になります．
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DeckConstraints {
+    /// デッキの最小枚数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_size: Option<usize>,
+    /// デッキの最大枚数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<usize>,
+    /// 1つのスートのカードを入れられる最大枚数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_per_suit: Option<usize>,
+    /// 1つのランクのカードを入れられる最大枚数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_per_rank: Option<usize>,
+    /// 同じカードを2回以上入れてはいけないかどうか
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub distinct: bool,
+    /// 入れてはいけないカード
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub banned_cards: Vec<Card>,
+    /// 入れてはいけないランク(1~13)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub banned_ranks: Vec<u32>,
+    /// デッキのコストの合計の上限
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub budget: Option<u32>,
+    /// rank_costsに無いカードのコスト
+    pub default_cost: u32,
+    /// ランクごとのカードのコスト
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub rank_costs: BTreeMap<u32, u32>,
+}
+
+impl Default for DeckConstraints {
+    fn default() -> Self {
+        DeckConstraints {
+            min_size: None,
+            max_size: None,
+            max_per_suit: None,
+            max_per_rank: None,
+            distinct: false,
+            banned_cards: Vec::new(),
+            banned_ranks: Vec::new(),
+            budget: None,
+            default_cost: 1,
+            rank_costs: BTreeMap::new(),
+        }
+    }
+}
+
+/// 破っているルール1つ分です．
+/// ruleは破ったルールの名前(DeckConstraintsのJSONでの名前)，cardsはルールを破っているカードのIDです．
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub rule: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cards: Vec<u32>,
+}
+
+impl Violation {
+    fn new(rule: &'static str, message: String, cards: Vec<u32>) -> Violation {
+        Violation { rule, message, cards }
+    }
+}
+
+/// デッキが制約を満たしていないときのエラーです．破っているルールをすべて持っています．
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConstraintError {
+    pub violations: Vec<Violation>,
+}
+
+impl fmt::Display for ConstraintError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Error: useCards breaks {} rule(s)", self.violations.len())?;
+        for violation in self.violations.iter() {
+            write!(f, "\n- {}", violation.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConstraintError {}
+
+impl DeckConstraints {
+    /// JSONファイルから制約を読み込みます．
+    pub fn load(path: &str) -> PorkerResult<DeckConstraints> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    /// カード1枚のコストを返します．
+    pub fn cost(&self, card: &Card) -> u32 {
+        self.rank_costs.get(&card.rank).copied().unwrap_or(self.default_cost)
+    }
+
+    /// デッキが破っているルールをすべて返します．すべて満たしていれば空です．
+    pub fn check(&self, use_cards: &[u32]) -> Vec<Violation> {
+        let cards: Vec<Card> = use_cards.iter().map(|id| Card::new(*id)).collect();
+        let mut violations = Vec::new();
+
+        if let Some(min_size) = self.min_size {
+            if cards.len() < min_size {
+                violations.push(Violation::new(
+                    "minSize",
+                    format!("useCards must have at least {} cards (got {})", min_size, cards.len()),
+                    Vec::new(),
+                ));
+            }
+        }
+        if let Some(max_size) = self.max_size {
+            if cards.len() > max_size {
+                violations.push(Violation::new(
+                    "maxSize",
+                    format!("useCards must have at most {} cards (got {})", max_size, cards.len()),
+                    Vec::new(),
+                ));
+            }
+        }
+
+        if let Some(max_per_suit) = self.max_per_suit {
+            for suit in [Suit::Spade, Suit::Heart, Suit::Diamond, Suit::Club] {
+                let ids = matching_ids(&cards, |x| x.suit == suit);
+                if ids.len() > max_per_suit {
+                    violations.push(Violation::new(
+                        "maxPerSuit",
+                        format!("at most {} cards of {:#} are allowed (got {})", max_per_suit, suit, ids.len()),
+                        ids,
+                    ));
+                }
+            }
+        }
+        if let Some(max_per_rank) = self.max_per_rank {
+            for rank in 1..=13 {
+                let ids = matching_ids(&cards, |x| x.rank == rank);
+                if ids.len() > max_per_rank {
+                    violations.push(Violation::new(
+                        "maxPerRank",
+                        format!(
+                            "at most {} cards of rank {} are allowed (got {})",
+                            max_per_rank,
+                            RANK_NAMES[rank as usize - 1],
+                            ids.len()
+                        ),
+                        ids,
+                    ));
+                }
+            }
+        }
+
+        if self.distinct {
+            let mut ids: Vec<u32> = use_cards
+                .iter()
+                .filter(|id| use_cards.iter().filter(|x| x == id).count() > 1)
+                .copied()
+                .collect();
+            ids.sort_unstable();
+            ids.dedup();
+            if !ids.is_empty() {
+                violations.push(Violation::new(
+                    "distinct",
+                    "the same card must not be used twice".to_string(),
+                    ids,
+                ));
+            }
+        }
+
+        let ids = matching_ids(&cards, |x| self.banned_cards.contains(x));
+        if !ids.is_empty() {
+            violations.push(Violation::new(
+                "bannedCards",
+                format!("banned cards are used: {}", notation(&ids)),
+                ids,
+            ));
+        }
+        let ids = matching_ids(&cards, |x| self.banned_ranks.contains(&x.rank));
+        if !ids.is_empty() {
+            violations.push(Violation::new(
+                "bannedRanks",
+                format!("cards of banned ranks are used: {}", notation(&ids)),
+                ids,
+            ));
+        }
+
+        if let Some(budget) = self.budget {
+            let cost: u32 = cards.iter().map(|x| self.cost(x)).sum();
+            if cost > budget {
+                violations.push(Violation::new(
+                    "budget",
+                    format!("total cost must be at most {} (got {})", budget, cost),
+                    Vec::new(),
+                ));
+            }
+        }
+
+        violations
+    }
+
+    /// デッキが制約を満たしているか確かめます．満たしていない場合は，破っているルールをすべて持つConstraintErrorを返します．
+    pub fn validate(&self, use_cards: &[u32]) -> PorkerResult<()> {
+        let violations = self.check(use_cards);
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(ConstraintError { violations }.into())
+        }
+    }
+}
+
+fn matching_ids(cards: &[Card], f: impl Fn(&Card) -> bool) -> Vec<u32> {
+    cards.iter().filter(|x| f(x)).map(|x| x.id).collect()
+}
+
+fn notation(ids: &[u32]) -> String {
+    ids.iter().map(|id| Card::new(*id).to_string()).collect::<Vec<_>>().join(", ")
+}
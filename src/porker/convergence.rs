@@ -0,0 +1,83 @@
+//! 回数を決めずに，期待スコアの推定が目標の精度に届くまでシミュレーションするモジュールです．
+//! 1回あたりの平均スコアの信頼区間の半分の幅を平均で割った値(相対精度)が，指定した値以下になったら止めます．
+
+use super::stats::{normal_quantile, Moments};
+use super::*;
+
+/// 信頼度を指定しなかったときの値です．
+pub const DEFAULT_CONFIDENCE: f64 = 0.95;
+
+/// 精度を確かめる間隔です．最初の確認もこの回数を配ってから行います．
+const CHECK_EVERY: u32 = 1000;
+
+/// 精度の目標と，実際に届いた精度です．
+#[derive(Debug, Clone, PartialEq)]
+pub struct Convergence {
+    /// 目標の相対精度(0.005なら±0.5%)
+    pub target: f64,
+    pub confidence: f64,
+    /// 止めた時点の相対精度．平均が0の場合は計算できないのでNoneです
+    pub achieved: Option<f64>,
+    /// 目標の精度に届いたかどうか．届く前に最大回数に達した場合はfalseです
+    pub converged: bool,
+}
+
+/// 平均スコアの推定の相対精度を返します．
+fn relative_precision(moments: &Moments, z: f64) -> Option<f64> {
+    let mean = moments.mean();
+    (mean != 0.).then(|| z * moments.std_error() / mean.abs())
+}
+
+/// 1回あたりの期待スコアの推定が，confidenceの信頼度で±targetの相対精度に届くまで手札を配ります．
+/// max_handsは配る手札の最大数で，max_loop_numまでに制限されます．
+/// 結果の回数は実際に配った手札の数です．
+pub fn converge_porker(
+    use_cards: &[u32],
+    target: f64,
+    confidence: f64,
+    max_hands: u32,
+    sheet: &ScoreSheet,
+) -> PorkerResult<(RoleCounts, i64, u32, Convergence)> {
+    if !(target > 0. && target < 1.) {
+        return Err(anyhow!("Error: precision must be between 0 and 1"));
+    }
+    if !(confidence > 0. && confidence < 1.) {
+        return Err(anyhow!("Error: confidence must be between 0 and 1"));
+    }
+
+    let max_hands = limit_loop_num(max_hands);
+    // 両側の信頼区間なので，残りの確率を半分ずつ両側に割り振る
+    let z = normal_quantile(0.5 + confidence / 2.);
+
+    let mut role_count = RoleCounts::new();
+    let mut moments = Moments::default();
+    let mut sum_score = 0;
+    let mut converged = false;
+    let mut loop_num = 0;
+
+    while loop_num < max_hands {
+        let cards = make_cards_from_id(&handout_cards(use_cards)?);
+        let role = count_judge_role(&cards, &mut role_count);
+        let score = sheet.score_hand(&cards, role);
+        sum_score += score;
+        moments.push(score as f64);
+        loop_num += 1;
+
+        if loop_num % CHECK_EVERY == 0 && relative_precision(&moments, z).iter().any(|x| *x <= target) {
+            converged = true;
+            break;
+        }
+    }
+
+    Ok((
+        role_count,
+        sum_score,
+        loop_num,
+        Convergence {
+            target,
+            confidence,
+            achieved: relative_precision(&moments, z),
+            converged,
+        },
+    ))
+}
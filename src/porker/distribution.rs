@@ -0,0 +1,183 @@
+//! スコアの分布を集計するモジュールです．
+//! allscoreは合計だけなので，手札1つごとのスコアのヒストグラムと，
+//! 決まった回数ずつ区切ったまとまり(バッチ)ごとの合計スコアの散らばりを計算します．
+
+use std::collections::BTreeMap;
+
+use super::stats::Moments;
+use super::*;
+
+/// バッチの大きさを指定しなかったときの，1バッチあたりの手札の数です．
+pub const DEFAULT_BATCH_SIZE: u32 = 100;
+
+/// ヒストグラムの1本分です．scoreのスコアになった手札の数がcountです．
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Bin {
+    pub score: i64,
+    pub count: u64,
+}
+
+/// 5，50，95パーセンタイルです．
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Percentiles {
+    pub p5: i64,
+    pub p50: i64,
+    pub p95: i64,
+}
+
+impl Percentiles {
+    /// 並べ替え済みの値からパーセンタイルを計算します．値が空の場合はNoneです．
+    pub fn from_sorted(sorted: &[i64]) -> Option<Percentiles> {
+        (!sorted.is_empty()).then(|| Percentiles {
+            p5: sorted_percentile(sorted, 5),
+            p50: sorted_percentile(sorted, 50),
+            p95: sorted_percentile(sorted, 95),
+        })
+    }
+}
+
+/// バッチごとの合計スコアの分布です．端数の手札(最後の半端なバッチ)は含めません．
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Batches {
+    pub size: u32,
+    pub count: u32,
+    pub mean: f64,
+    pub variance: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percentiles: Option<Percentiles>,
+}
+
+/// スコアの分布です．mean，variance，percentilesは手札1つあたりのスコアについての値です．
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Distribution {
+    pub histogram: Vec<Bin>,
+    pub mean: f64,
+    pub variance: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percentiles: Option<Percentiles>,
+    pub batches: Batches,
+}
+
+/// 手札のスコアを1つずつ受け取って，分布を集計します．
+#[derive(Debug, Clone)]
+pub struct DistributionBuilder {
+    histogram: BTreeMap<i64, u64>,
+    moments: Moments,
+    batch_size: u32,
+    batch_sum: i64,
+    batch_len: u32,
+    batch_totals: Vec<i64>,
+}
+
+impl DistributionBuilder {
+    pub fn new(batch_size: u32) -> DistributionBuilder {
+        DistributionBuilder {
+            histogram: BTreeMap::new(),
+            moments: Moments::default(),
+            batch_size: batch_size.max(1),
+            batch_sum: 0,
+            batch_len: 0,
+            batch_totals: Vec::new(),
+        }
+    }
+
+    /// 手札1つ分のスコアを追加します．
+    pub fn push(&mut self, score: i64) {
+        *self.histogram.entry(score).or_insert(0) += 1;
+        self.moments.push(score as f64);
+
+        self.batch_sum += score;
+        self.batch_len += 1;
+        if self.batch_len == self.batch_size {
+            self.batch_totals.push(self.batch_sum);
+            self.batch_sum = 0;
+            self.batch_len = 0;
+        }
+    }
+
+    pub fn build(mut self) -> Distribution {
+        let total = self.moments.count;
+        let percentiles = (total > 0).then(|| Percentiles {
+            p5: histogram_percentile(&self.histogram, total, 5),
+            p50: histogram_percentile(&self.histogram, total, 50),
+            p95: histogram_percentile(&self.histogram, total, 95),
+        });
+
+        let mut batch_moments = Moments::default();
+        for x in self.batch_totals.iter() {
+            batch_moments.push(*x as f64);
+        }
+        self.batch_totals.sort_unstable();
+        let batch_percentiles = Percentiles::from_sorted(&self.batch_totals);
+
+        Distribution {
+            histogram: self
+                .histogram
+                .into_iter()
+                .map(|(score, count)| Bin { score, count })
+                .collect(),
+            mean: self.moments.mean(),
+            variance: self.moments.variance(),
+            percentiles,
+            batches: Batches {
+                size: self.batch_size,
+                count: self.batch_totals.len() as u32,
+                mean: batch_moments.mean(),
+                variance: batch_moments.variance(),
+                percentiles: batch_percentiles,
+            },
+        }
+    }
+}
+
+/// 最近順位法で，total個の値のうち小さい方からp%の位置の順位(1始まり)を返します．
+fn nearest_rank(total: u64, p: u64) -> u64 {
+    (total * p).div_ceil(100).max(1)
+}
+
+/// ヒストグラムからpパーセンタイルを返します．totalはヒストグラムの数の合計です．
+fn histogram_percentile(histogram: &BTreeMap<i64, u64>, total: u64, p: u64) -> i64 {
+    let rank = nearest_rank(total, p);
+    let mut seen = 0;
+    for (score, count) in histogram.iter() {
+        seen += count;
+        if seen >= rank {
+            return *score;
+        }
+    }
+    *histogram.keys().next_back().unwrap()
+}
+
+/// 並べ替え済みの値からpパーセンタイルを返します．
+fn sorted_percentile(sorted: &[i64], p: u64) -> i64 {
+    sorted[nearest_rank(sorted.len() as u64, p) as usize - 1]
+}
+
+/// million_porkerと同じですが，役の出現回数とスコアの合計に加えて，スコアの分布も返します．
+/// batch_sizeはバッチ1つあたりの手札の数で，省略した場合はDEFAULT_BATCH_SIZEです．
+pub fn distribution_porker<R: Rng + ?Sized>(
+    use_cards: &[u32],
+    num: u32,
+    sheet: &ScoreSheet,
+    batch_size: Option<u32>,
+    rng: &mut R,
+) -> PorkerResult<(RoleCounts, i64, u32, Distribution)> {
+    let batch_size = batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+    if batch_size == 0 {
+        return Err(anyhow!("Error: batchSize must be at least 1"));
+    }
+
+    let loop_num = limit_loop_num(num);
+    let mut builder = DistributionBuilder::new(batch_size);
+    let (role_count, sum_score, loop_num) = run_porker_with_rng(
+        use_cards,
+        loop_num,
+        sheet,
+        loop_num,
+        |_, _, _| true,
+        |_, _, score| builder.push(score),
+        rng,
+    )?;
+
+    Ok((role_count, sum_score, loop_num, builder.build()))
+}
@@ -0,0 +1,109 @@
+//! 役ごとの出現回数を保持するRoleCounts型のモジュールです．
+//! 中身は配列ですが，添字にはRoleを使うので，役を追加しても並び順がずれることはありません．
+
+use std::collections::BTreeMap;
+use std::ops::{AddAssign, Index, IndexMut};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::Role;
+
+/// それぞれの役が何回出たかを保持する構造体です．
+/// role_count[Role::OnePair] のようにRoleで読み書きできます．
+/// JSONではRoleの名前をキーにしたオブジェクト({"nopair": 0, "onepair": 0, ...})になります．
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RoleCounts([u32; Role::COUNT]);
+
+impl RoleCounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 役の出現回数を1増やします．
+    pub fn increment(&mut self, role: Role) {
+        self[role] += 1;
+    }
+
+    /// 別の結果の回数を足し合わせます．
+    pub fn merge(&mut self, other: &RoleCounts) {
+        for role in Role::ALL {
+            self[role] += other[role];
+        }
+    }
+
+    /// すべての役の回数の合計(実行回数)を返します．
+    pub fn total(&self) -> u64 {
+        self.0.iter().map(|x| *x as u64).sum()
+    }
+
+    /// 役と回数の組を，Roleの並び順に返します．
+    pub fn iter(&self) -> impl Iterator<Item = (Role, u32)> + '_ {
+        Role::ALL.iter().map(move |role| (*role, self[*role]))
+    }
+
+    /// 役が出た割合(0~1)を返します．1回も実行していない場合は0です．
+    pub fn probability(&self, role: Role) -> f64 {
+        let total = self.total();
+        if total == 0 {
+            0.
+        } else {
+            self[role] as f64 / total as f64
+        }
+    }
+
+    /// すべての役について，役と出た割合の組をRoleの並び順に返します．
+    pub fn probabilities(&self) -> impl Iterator<Item = (Role, f64)> + '_ {
+        Role::ALL.iter().map(move |role| (*role, self.probability(*role)))
+    }
+
+    /// 役ごとに，otherの回数からの差(self - other)を返します．
+    pub fn diff(&self, other: &RoleCounts) -> BTreeMap<Role, i64> {
+        Role::ALL
+            .iter()
+            .map(|role| (*role, self[*role] as i64 - other[*role] as i64))
+            .collect()
+    }
+}
+
+impl Index<Role> for RoleCounts {
+    type Output = u32;
+
+    fn index(&self, role: Role) -> &u32 {
+        &self.0[role as usize]
+    }
+}
+
+impl IndexMut<Role> for RoleCounts {
+    fn index_mut(&mut self, role: Role) -> &mut u32 {
+        &mut self.0[role as usize]
+    }
+}
+
+impl AddAssign for RoleCounts {
+    fn add_assign(&mut self, other: RoleCounts) {
+        self.merge(&other);
+    }
+}
+
+impl AddAssign<&RoleCounts> for RoleCounts {
+    fn add_assign(&mut self, other: &RoleCounts) {
+        self.merge(other);
+    }
+}
+
+impl Serialize for RoleCounts {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+/// キーが足りない場合は，その役の回数を0として読み込みます．
+impl<'de> Deserialize<'de> for RoleCounts {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut role_count = RoleCounts::new();
+        for (role, count) in BTreeMap::<Role, u32>::deserialize(deserializer)? {
+            role_count[role] = count;
+        }
+        Ok(role_count)
+    }
+}
@@ -0,0 +1,367 @@
+//! 分散を小さくする工夫をしたシミュレーションのモジュールです．
+//! ロイヤルストレートフラッシュのような珍しい役は，普通に手札を配るだけでは100万回でも回数がばらつくので，
+//! 正確に計算できる確率を使って，同じ回数でも誤差の小さい推定をします．
+//!
+//! - stratified: 手札のスートの内訳(スペード2枚，ハート3枚など)ごとに層を分けます．
+//!   層の確率はデッキから正確に計算できるので，層ごとの結果を確率で重み付けして足し合わせます．
+//!   回数の1/4はすべての層に均等に割り振るので，フラッシュの層のような珍しい層も多めに配られます．
+//! - controlVariate: 「5枚が同じスート」と「同じランクの2枚の組の数」を制御変数にします．
+//!   この2つの期待値はデッキから正確に計算できるので，そこからのずれでスコアや役の回数を補正します．
+//!   補正の係数は最初の1割(予備実行)だけで決め，残りの手札で推定するので，推定は偏りません．
+//!
+//! どちらも，同じ精度を普通のモンテカルロ法で出すのに必要な回数を有効サンプルサイズとして返します．
+//! 手札は重複を取り除いたデッキから一様に選ぶので，useCardsに同じカードがあっても1枚として扱います．
+//! 対になる手札を使うantithetic法は，手札の選び方に自然な「反対」が無いので実装していません．
+
+use std::collections::BTreeMap;
+
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+use super::stats::Moments;
+use super::*;
+
+/// 推定の方法です．
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Strategy {
+    /// 普通に手札を配るだけの方法
+    Plain,
+    Stratified,
+    ControlVariate,
+}
+
+/// 推定の結果です．確率は手札1つでそれぞれの役が出る確率の推定です．
+/// 偏りを出さないよう0~1に丸めないので，回数が少ないと少しだけマイナスになることがあります．
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+    pub strategy: Strategy,
+    /// 実際に配った手札の数(予備実行も含みます)
+    pub loop_num: u32,
+    pub seed: u64,
+    pub mean_score: f64,
+    pub std_error: f64,
+    pub effective_sample_size: f64,
+    pub probabilities: BTreeMap<Role, f64>,
+}
+
+/// 層ごとに最低限配る手札の数です．層の中の分散を計算するのに2回は必要です．
+const MIN_PER_STRATUM: u32 = 2;
+
+/// controlVariateで係数を決めるのに使う予備実行の割合です．
+const PILOT_FRACTION: u32 = 10;
+
+/// 使うカードの中から一様に手札を選び，指定した方法で1回あたりの期待スコアと役の確率を推定します．
+/// 回数の上限は/postcardsと同じです．シードを省略した場合はランダムに決め，結果に含めて返します．
+pub fn estimate_porker(
+    use_cards: &[u32],
+    num: u32,
+    strategy: Strategy,
+    seed: Option<u64>,
+    sheet: &ScoreSheet,
+) -> PorkerResult<Estimate> {
+    let mut deck = use_cards.to_vec();
+    deck.sort_unstable();
+    deck.dedup();
+    if deck.len() < 5 {
+        return Err(anyhow!("Error: Invalid useCards"));
+    }
+
+    let loop_num = limit_loop_num(num);
+    let seed = resolve_seed(seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut estimate = match strategy {
+        Strategy::Plain => plain(&deck, loop_num, &mut rng, sheet)?,
+        Strategy::Stratified => stratified(&deck, loop_num, &mut rng, sheet)?,
+        Strategy::ControlVariate => control_variate(&deck, loop_num, &mut rng, sheet)?,
+    };
+    estimate.seed = seed;
+    Ok(estimate)
+}
+
+/// 手札1つ分の結果です．
+struct Sample {
+    role: Role,
+    score: f64,
+}
+
+/// 手札を判定して，役とスコアを返します．
+fn judge(cards_id: &[u32], sheet: &ScoreSheet) -> Sample {
+    let cards = make_cards_from_id(cards_id.try_into().unwrap());
+    let role = classify(&cards);
+    Sample {
+        role,
+        score: sheet.score_hand(&cards, role) as f64,
+    }
+}
+
+/// デッキから一様に5枚選びます．
+fn deal(deck: &[u32], rng: &mut StdRng) -> Vec<u32> {
+    deck.choose_multiple(rng, 5).copied().collect()
+}
+
+fn plain(deck: &[u32], loop_num: u32, rng: &mut StdRng, sheet: &ScoreSheet) -> PorkerResult<Estimate> {
+    if loop_num == 0 {
+        return Err(anyhow!("Error: num must be at least 1"));
+    }
+
+    let mut moments = Moments::default();
+    let mut role_count = RoleCounts::new();
+    for _ in 0..loop_num {
+        let sample = judge(&deal(deck, rng), sheet);
+        moments.push(sample.score);
+        role_count.increment(sample.role);
+    }
+
+    Ok(Estimate {
+        strategy: Strategy::Plain,
+        loop_num,
+        seed: 0,
+        mean_score: moments.mean(),
+        std_error: moments.std_error(),
+        effective_sample_size: loop_num as f64,
+        probabilities: role_count.probabilities().collect(),
+    })
+}
+
+/// 二項係数C(n, k)を返します．
+fn binomial(n: usize, k: usize) -> f64 {
+    if k > n {
+        return 0.;
+    }
+    (0..k).fold(1., |acc, i| acc * (n - i) as f64 / (i + 1) as f64)
+}
+
+/// スートの内訳1つ分の層です．
+struct Stratum {
+    /// スートごとの枚数(Suitの並び順)
+    counts: [usize; 4],
+    /// この内訳の手札が配られる確率
+    weight: f64,
+    hands: u32,
+    moments: Moments,
+    sum_squares: f64,
+    role_count: RoleCounts,
+}
+
+fn stratified(deck: &[u32], loop_num: u32, rng: &mut StdRng, sheet: &ScoreSheet) -> PorkerResult<Estimate> {
+    let mut suits: [Vec<u32>; 4] = Default::default();
+    for id in deck.iter() {
+        suits[Card::new(*id).suit as usize].push(*id);
+    }
+
+    // 5枚のスートの内訳をすべて挙げ，デッキで作れるものを層にする
+    let total = binomial(deck.len(), 5);
+    let mut strata = Vec::new();
+    for a in 0..=5 {
+        for b in 0..=5 - a {
+            for c in 0..=5 - a - b {
+                let counts = [a, b, c, 5 - a - b - c];
+                let weight = counts
+                    .iter()
+                    .zip(suits.iter())
+                    .map(|(k, cards)| binomial(cards.len(), *k))
+                    .product::<f64>()
+                    / total;
+                if weight > 0. {
+                    strata.push(Stratum {
+                        counts,
+                        weight,
+                        hands: 0,
+                        moments: Moments::default(),
+                        sum_squares: 0.,
+                        role_count: RoleCounts::new(),
+                    });
+                }
+            }
+        }
+    }
+
+    let strata_num = strata.len() as u32;
+    if loop_num < MIN_PER_STRATUM * strata_num {
+        return Err(anyhow!(
+            "Error: stratified sampling needs num >= {} for this deck",
+            MIN_PER_STRATUM * strata_num
+        ));
+    }
+    // 1/4はすべての層に均等に，残りは確率に比例して割り振る
+    let even = (loop_num / (4 * strata_num)).max(MIN_PER_STRATUM);
+    let rest = (loop_num - even * strata_num) as f64;
+    for stratum in strata.iter_mut() {
+        stratum.hands = even + (rest * stratum.weight) as u32;
+    }
+
+    for stratum in strata.iter_mut() {
+        for _ in 0..stratum.hands {
+            let mut cards_id = Vec::with_capacity(5);
+            for (k, cards) in stratum.counts.iter().zip(suits.iter()) {
+                cards_id.extend(cards.choose_multiple(rng, *k));
+            }
+            let sample = judge(&cards_id, sheet);
+            stratum.moments.push(sample.score);
+            stratum.sum_squares += sample.score * sample.score;
+            stratum.role_count.increment(sample.role);
+        }
+    }
+
+    let mean_score: f64 = strata.iter().map(|x| x.weight * x.moments.mean()).sum();
+    let variance: f64 = strata
+        .iter()
+        .map(|x| x.weight * x.weight * x.moments.variance() / x.hands as f64)
+        .sum();
+    // 普通に配った場合の，手札1つのスコアの分散
+    let mean_squares: f64 = strata.iter().map(|x| x.weight * x.sum_squares / x.hands as f64).sum();
+    let plain_variance = mean_squares - mean_score * mean_score;
+
+    let probabilities = Role::ALL
+        .iter()
+        .map(|role| {
+            let p = strata.iter().map(|x| x.weight * x.role_count.probability(*role)).sum();
+            (*role, p)
+        })
+        .collect();
+
+    Ok(Estimate {
+        strategy: Strategy::Stratified,
+        loop_num: strata.iter().map(|x| x.hands).sum(),
+        seed: 0,
+        mean_score,
+        std_error: variance.sqrt(),
+        effective_sample_size: effective_sample_size(plain_variance, variance),
+        probabilities,
+    })
+}
+
+/// 同じ精度を普通のモンテカルロ法で出すのに必要な回数です．推定の分散が0の場合は無限大です．
+fn effective_sample_size(plain_variance: f64, variance: f64) -> f64 {
+    if variance > 0. {
+        plain_variance / variance
+    } else {
+        f64::INFINITY
+    }
+}
+
+/// 制御変数の値です．5枚が同じスートかどうか(0か1)と，同じランクの2枚の組の数です．
+fn controls(cards_id: &[u32]) -> [f64; 2] {
+    let cards: Vec<Card> = cards_id.iter().map(|id| Card::new(*id)).collect();
+    let single_suit = cards.iter().all(|x| x.suit == cards[0].suit);
+    let mut pairs = 0;
+    for i in 0..cards.len() {
+        for j in i + 1..cards.len() {
+            if cards[i].rank == cards[j].rank {
+                pairs += 1;
+            }
+        }
+    }
+    [single_suit as u32 as f64, pairs as f64]
+}
+
+/// 制御変数の正確な期待値です．
+fn control_means(deck: &[u32]) -> [f64; 2] {
+    let mut suit_num = [0; 4];
+    let mut rank_num = [0; 13];
+    for id in deck.iter() {
+        let card = Card::new(*id);
+        suit_num[card.suit as usize] += 1;
+        rank_num[card.rank as usize - 1] += 1;
+    }
+    let single_suit = suit_num.iter().map(|n| binomial(*n, 5)).sum::<f64>() / binomial(deck.len(), 5);
+    // 5枚の中の2枚の組はC(5, 2) = 10組で，それぞれが同じランクになる確率は同じ
+    let pairs = 10. * rank_num.iter().map(|n| binomial(*n, 2)).sum::<f64>() / binomial(deck.len(), 2);
+    [single_suit, pairs]
+}
+
+/// 予備実行の結果から，補正の係数を最小二乗法で求めます．
+/// 値が変わらない制御変数は使わず，どちらも変わらない場合は補正しません．
+fn control_coefficients(pilot_controls: &[[f64; 2]], values: &[f64]) -> [f64; 2] {
+    let n = values.len() as f64;
+    let mean_y = [0, 1].map(|k| pilot_controls.iter().map(|y| y[k]).sum::<f64>() / n);
+    let mean_x = values.iter().sum::<f64>() / n;
+
+    let mut syy = [[0.; 2]; 2];
+    let mut syx = [0.; 2];
+    for (y, x) in pilot_controls.iter().zip(values.iter()) {
+        let dy = [y[0] - mean_y[0], y[1] - mean_y[1]];
+        for k in 0..2 {
+            syx[k] += dy[k] * (x - mean_x);
+            for l in 0..2 {
+                syy[k][l] += dy[k] * dy[l];
+            }
+        }
+    }
+
+    let det = syy[0][0] * syy[1][1] - syy[0][1] * syy[1][0];
+    if det.abs() > 1e-9 * syy[0][0] * syy[1][1] {
+        [
+            (syy[1][1] * syx[0] - syy[0][1] * syx[1]) / det,
+            (syy[0][0] * syx[1] - syy[1][0] * syx[0]) / det,
+        ]
+    } else if syy[0][0] > 0. {
+        [syx[0] / syy[0][0], 0.]
+    } else if syy[1][1] > 0. {
+        [0., syx[1] / syy[1][1]]
+    } else {
+        [0., 0.]
+    }
+}
+
+fn control_variate(deck: &[u32], loop_num: u32, rng: &mut StdRng, sheet: &ScoreSheet) -> PorkerResult<Estimate> {
+    let pilot_num = loop_num / PILOT_FRACTION;
+    if pilot_num < 2 {
+        return Err(anyhow!(
+            "Error: control variates need num >= {}",
+            2 * PILOT_FRACTION
+        ));
+    }
+    let mu = control_means(deck);
+
+    // 予備実行で，スコアと役ごとの補正の係数を決める
+    let mut pilot_controls = Vec::with_capacity(pilot_num as usize);
+    let mut pilot_samples = Vec::with_capacity(pilot_num as usize);
+    for _ in 0..pilot_num {
+        let cards_id = deal(deck, rng);
+        pilot_controls.push(controls(&cards_id));
+        pilot_samples.push(judge(&cards_id, sheet));
+    }
+    let scores: Vec<f64> = pilot_samples.iter().map(|x| x.score).collect();
+    let score_beta = control_coefficients(&pilot_controls, &scores);
+    let role_betas: Vec<[f64; 2]> = Role::ALL
+        .iter()
+        .map(|role| {
+            let indicators: Vec<f64> = pilot_samples.iter().map(|x| (x.role == *role) as u32 as f64).collect();
+            control_coefficients(&pilot_controls, &indicators)
+        })
+        .collect();
+
+    let adjust = |x: f64, beta: &[f64; 2], y: &[f64; 2]| x - beta[0] * (y[0] - mu[0]) - beta[1] * (y[1] - mu[1]);
+
+    let mut raw = Moments::default();
+    let mut adjusted = Moments::default();
+    let mut role_sums = vec![0.; Role::ALL.len()];
+    let main_num = loop_num - pilot_num;
+    for _ in 0..main_num {
+        let cards_id = deal(deck, rng);
+        let y = controls(&cards_id);
+        let sample = judge(&cards_id, sheet);
+        raw.push(sample.score);
+        adjusted.push(adjust(sample.score, &score_beta, &y));
+        for (i, role) in Role::ALL.iter().enumerate() {
+            role_sums[i] += adjust((sample.role == *role) as u32 as f64, &role_betas[i], &y);
+        }
+    }
+
+    let variance = adjusted.variance() / main_num as f64;
+    Ok(Estimate {
+        strategy: Strategy::ControlVariate,
+        loop_num,
+        seed: 0,
+        mean_score: adjusted.mean(),
+        std_error: variance.sqrt(),
+        effective_sample_size: effective_sample_size(raw.variance(), variance),
+        probabilities: Role::ALL
+            .iter()
+            .zip(role_sums.iter())
+            .map(|(role, sum)| (*role, sum / main_num as f64))
+            .collect(),
+    })
+}
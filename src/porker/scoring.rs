@@ -0,0 +1,140 @@
+//! 手札1つごとにスコアを計算するスコアシートのモジュールです．
+//! 役だけでなく実際のカードを見てスコアを計算するので，
+//! 「ハートのフラッシュは2倍」「スペードのQが入っていたら-50点」のようなルールを書けます．
+
+use std::collections::BTreeMap;
+
+use super::*;
+
+/// 手札1つのスコアの絶対値の上限です．
+/// u32で数えられるだけ配っても合計がi64に収まるよう，スコアシートを読み込むときにこれを超えうるシートを弾きます．
+pub const MAX_HAND_SCORE: i64 = 1_000_000_000;
+
+/// スコアのルール1つ分です．JSONでは"type"でルールの種類を表します．
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ScoreRule {
+    /// 役を作っているカードがすべてsuitの場合，スコアをmultiplier倍にします．roleを省略するとすべての役に使います．
+    /// 例: {"type": "suitMultiplier", "role": "flush", "suit": "heart", "multiplier": 2}
+    SuitMultiplier {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        role: Option<Role>,
+        suit: Suit,
+        multiplier: i64,
+    },
+    /// 手札にcardが入っている場合，pointsを足します．マイナスにすると減点になります．
+    /// 例: {"type": "cardPoints", "card": "Qs", "points": -50}
+    CardPoints { card: Card, points: i64 },
+    /// 役がroleで，役を作っているカードがすべてrankの場合，scoreAsの役のスコアにします．
+    /// 例: {"type": "rankRole", "role": "onepair", "rank": 1, "scoreAs": "threepair"}
+    RankRole {
+        role: Role,
+        rank: u32,
+        #[serde(rename = "scoreAs")]
+        score_as: Role,
+    },
+}
+
+/// 手札のスコアの計算方法です．
+/// 役ごとの基本のスコア(roleScoresに無い役はrole_scoreの値)を，rankRole，suitMultiplier，cardPointsの順に変えます．
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ScoreSheet {
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub role_scores: BTreeMap<Role, i64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<ScoreRule>,
+}
+
+impl ScoreSheet {
+    /// JSONファイルからスコアシートを読み込みます．
+    pub fn load(path: &str) -> PorkerResult<ScoreSheet> {
+        let text = std::fs::read_to_string(path)?;
+        let sheet: ScoreSheet = serde_json::from_str(&text)?;
+        sheet.validate()?;
+        Ok(sheet)
+    }
+
+    /// どの手札でもスコアの絶対値がMAX_HAND_SCORE以下になることを確かめます．
+    /// 一番大きい役のスコアにすべての倍率を掛け，すべての点を足した値で確かめるので，実際より厳しめです．
+    pub fn validate(&self) -> PorkerResult<()> {
+        let out_of_range = || anyhow!("Error: Score sheet can exceed {} points per hand", MAX_HAND_SCORE);
+
+        let mut max = 0;
+        for role in Role::ALL {
+            max = max.max(self.role_score(role).checked_abs().ok_or_else(out_of_range)?);
+        }
+        for rule in self.rules.iter() {
+            if let ScoreRule::SuitMultiplier { multiplier, .. } = rule {
+                max = multiplier
+                    .checked_abs()
+                    .and_then(|x| max.checked_mul(x))
+                    .ok_or_else(out_of_range)?;
+            }
+        }
+        for rule in self.rules.iter() {
+            if let ScoreRule::CardPoints { points, .. } = rule {
+                max = points
+                    .checked_abs()
+                    .and_then(|x| max.checked_add(x))
+                    .ok_or_else(out_of_range)?;
+            }
+        }
+        if max > MAX_HAND_SCORE {
+            return Err(out_of_range());
+        }
+        Ok(())
+    }
+
+    /// 役の基本のスコアを返します．
+    pub fn role_score(&self, role: Role) -> i64 {
+        self.role_scores
+            .get(&role)
+            .copied()
+            .unwrap_or_else(|| role_score(role) as i64)
+    }
+
+    /// 手札1つのスコアを返します．roleはその手札の役です．
+    pub fn score_hand(&self, cards: &[Card; 5], role: Role) -> i64 {
+        if self.rules.is_empty() {
+            return self.role_score(role);
+        }
+
+        let role_cards = classify_with_reason(cards).cards;
+
+        let base_role = self
+            .rules
+            .iter()
+            .find_map(|rule| match rule {
+                ScoreRule::RankRole {
+                    role: target,
+                    rank,
+                    score_as,
+                } if *target == role && role_cards.iter().all(|x| x.rank == *rank) => Some(*score_as),
+                _ => None,
+            })
+            .unwrap_or(role);
+        let mut score = self.role_score(base_role);
+
+        for rule in self.rules.iter() {
+            match rule {
+                ScoreRule::SuitMultiplier {
+                    role: target,
+                    suit,
+                    multiplier,
+                } if target.iter().all(|x| *x == role) && role_cards.iter().all(|x| x.suit == *suit) => {
+                    score *= multiplier;
+                }
+                _ => {}
+            }
+        }
+        for rule in self.rules.iter() {
+            match rule {
+                ScoreRule::CardPoints { card, points } if cards.contains(card) => score += points,
+                _ => {}
+            }
+        }
+
+        score
+    }
+}
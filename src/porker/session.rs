@@ -0,0 +1,132 @@
+//! 1回のゲーム(セッション)を手札K回として，セッションを何回も繰り返すモジュールです．
+//! 手札ごとに参加費(ante)を払い，持ち点(bankroll)が尽きたらそのセッションは終わりになるので，
+//! allscoreだけでは分からない「1ゲームでどのくらいの合計になるか」「破産する確率はどのくらいか」を調べられます．
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use super::distribution::Percentiles;
+use super::stats::Moments;
+use super::*;
+
+/// セッションの設定です．
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionConfig {
+    /// 1セッションあたりの手札の数
+    pub hands: u32,
+    /// セッションを繰り返す回数
+    pub sessions: u32,
+    /// 手札1つごとに払う参加費
+    pub ante: i64,
+    /// 最初の持ち点．省略した場合は破産を考えません
+    pub bankroll: Option<i64>,
+    pub seed: Option<u64>,
+}
+
+/// セッション1回分の結果です．
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionResult {
+    /// 何回目のセッションか(0始まり)
+    pub index: u32,
+    /// 参加費を引いた合計スコア
+    pub total: i64,
+    /// 実際に配られた手札の数．破産した場合はhandsより少なくなります
+    pub hands: u32,
+    pub ruined: bool,
+    pub role_count: RoleCounts,
+}
+
+/// すべてのセッションの結果をまとめたものです．
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionSummary {
+    pub sessions: u32,
+    pub hands: u32,
+    pub seed: u64,
+    pub mean: f64,
+    pub variance: f64,
+    pub percentiles: Percentiles,
+    /// 破産したセッションの割合．持ち点を指定しなかった場合はNoneです
+    pub risk_of_ruin: Option<f64>,
+    pub best: SessionResult,
+    pub worst: SessionResult,
+}
+
+/// 使うカードで，手札config.hands回のセッションをconfig.sessions回繰り返します．
+/// 持ち点を指定した場合は，参加費を払えなくなるか持ち点がマイナスになった時点でそのセッションは破産です．
+/// 手札の総数(hands * sessions)はmax_loop_numまでです．
+pub fn simulate_sessions(use_cards: &[u32], config: &SessionConfig, sheet: &ScoreSheet) -> PorkerResult<SessionSummary> {
+    if config.hands == 0 || config.sessions == 0 {
+        return Err(anyhow!("Error: hands and sessions must be at least 1"));
+    }
+    let max = limits().max_loop_num;
+    if config.hands as u64 * config.sessions as u64 > max as u64 {
+        return Err(anyhow!("Error: hands * sessions must be at most {}", max));
+    }
+    if config.ante < 0 {
+        return Err(anyhow!("Error: ante must not be negative"));
+    }
+
+    let seed = resolve_seed(config.seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut totals = Vec::with_capacity(config.sessions as usize);
+    let mut moments = Moments::default();
+    let mut ruined_count = 0;
+    let mut best: Option<SessionResult> = None;
+    let mut worst: Option<SessionResult> = None;
+
+    for index in 0..config.sessions {
+        let mut result = SessionResult {
+            index,
+            total: 0,
+            hands: 0,
+            ruined: false,
+            role_count: RoleCounts::new(),
+        };
+
+        for _ in 0..config.hands {
+            if let Some(bankroll) = config.bankroll {
+                if bankroll + result.total < config.ante {
+                    result.ruined = true;
+                    break;
+                }
+            }
+            let cards = make_cards_from_id(&handout_cards_with_rng(use_cards, &mut rng)?);
+            let role = count_judge_role(&cards, &mut result.role_count);
+            result.total += sheet.score_hand(&cards, role) - config.ante;
+            result.hands += 1;
+        }
+        // 最後の手札でマイナスになった場合も破産
+        if let Some(bankroll) = config.bankroll {
+            result.ruined |= bankroll + result.total < 0;
+        }
+
+        if result.ruined {
+            ruined_count += 1;
+        }
+        totals.push(result.total);
+        moments.push(result.total as f64);
+
+        if best.iter().all(|x| result.total > x.total) {
+            best = Some(result.clone());
+        }
+        if worst.iter().all(|x| result.total < x.total) {
+            worst = Some(result);
+        }
+    }
+
+    totals.sort_unstable();
+
+    Ok(SessionSummary {
+        sessions: config.sessions,
+        hands: config.hands,
+        seed,
+        mean: moments.mean(),
+        variance: moments.variance(),
+        percentiles: Percentiles::from_sorted(&totals).unwrap(),
+        risk_of_ruin: config
+            .bankroll
+            .map(|_| ruined_count as f64 / config.sessions as f64),
+        best: best.unwrap(),
+        worst: worst.unwrap(),
+    })
+}
@@ -0,0 +1,100 @@
+//! 2つのデッキで対戦するモジュールです．
+//! 毎回，それぞれのデッキから5枚ずつ手札を配り，役とキッカーまで含めた強さで勝ち負けを決めます．
+
+use std::cmp::Ordering;
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use super::*;
+
+/// 片方のプレイヤーの結果です．
+#[derive(Debug, Clone, PartialEq)]
+pub struct SideResult {
+    pub role_count: RoleCounts,
+    pub sum_score: i64,
+    /// 勝った回数
+    pub wins: u32,
+    /// 勝ったときの役ごとの回数
+    pub wins_by_role: RoleCounts,
+}
+
+/// 対戦全体の結果です．
+#[derive(Debug, Clone, PartialEq)]
+pub struct Showdown {
+    pub loop_num: u32,
+    pub seed: u64,
+    pub ties: u32,
+    /// 1人目と2人目の結果
+    pub sides: [SideResult; 2],
+}
+
+impl Showdown {
+    /// 1人目から見た勝ち，負け，引き分けの割合(0~1)を返します．
+    pub fn rates(&self) -> (f64, f64, f64) {
+        if self.loop_num == 0 {
+            return (0., 0., 0.);
+        }
+        let n = self.loop_num as f64;
+        (
+            self.sides[0].wins as f64 / n,
+            self.sides[1].wins as f64 / n,
+            self.ties as f64 / n,
+        )
+    }
+}
+
+/// 2つのデッキで指定回数対戦します．回数の上限はmax_loop_numです．
+/// スコアはスコアシートで計算します．勝ち負けは役の強さで決めるので，スコアシートには影響されません．
+/// シードを省略した場合はランダムに決め，結果に含めて返します．
+pub fn showdown(decks: &[Vec<u32>], num: u32, seed: Option<u64>, sheet: &ScoreSheet) -> PorkerResult<Showdown> {
+    if decks.len() != 2 {
+        return Err(anyhow!("Error: Exactly 2 decks are required"));
+    }
+
+    let loop_num = limit_loop_num(num);
+    let seed = resolve_seed(seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut role_counts = [RoleCounts::new(); 2];
+    let mut sum_scores = [0; 2];
+    let mut wins_by_role = [RoleCounts::new(); 2];
+    let mut wins = [0; 2];
+    let mut ties = 0;
+
+    for _ in 0..loop_num {
+        let mut ranks = Vec::with_capacity(2);
+        for (i, deck) in decks.iter().enumerate() {
+            let cards_id = handout_cards_with_rng(deck, &mut rng)?;
+            let cards = make_cards_from_id(&cards_id);
+            let rank = rank_hand(&cards);
+            role_counts[i].increment(rank.role);
+            sum_scores[i] += sheet.score_hand(&cards, rank.role);
+            ranks.push(rank);
+        }
+
+        let winner = match ranks[0].cmp(&ranks[1]) {
+            Ordering::Greater => 0,
+            Ordering::Less => 1,
+            Ordering::Equal => {
+                ties += 1;
+                continue;
+            }
+        };
+        wins[winner] += 1;
+        wins_by_role[winner].increment(ranks[winner].role);
+    }
+
+    let side = |i: usize| SideResult {
+        role_count: role_counts[i],
+        sum_score: sum_scores[i],
+        wins: wins[i],
+        wins_by_role: wins_by_role[i],
+    };
+
+    Ok(Showdown {
+        loop_num,
+        seed,
+        ties,
+        sides: [side(0), side(1)],
+    })
+}
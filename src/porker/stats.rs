@@ -0,0 +1,125 @@
+//! シミュレーション結果の統計処理に使う関数をまとめたモジュールです．
+
+/// 標準正規分布の累積分布関数です．
+pub fn normal_cdf(x: f64) -> f64 {
+    0.5 * erfc(-x / std::f64::consts::SQRT_2)
+}
+
+/// 両側検定のp値を，z値から正規近似で計算します．
+pub fn two_sided_p_value(z: f64) -> f64 {
+    (2. * (1. - normal_cdf(z.abs()))).clamp(0., 1.)
+}
+
+/// 標準正規分布の分位点(累積分布関数の逆関数)です．pは0より大きく1より小さい値です．
+/// Acklamのアルゴリズムによる近似で，相対誤差は1.15e-9以下です．
+pub fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1,
+        2.209_460_984_245_205e2,
+        -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2,
+        -3.066_479_806_614_716e1,
+        2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1,
+        1.615_858_368_580_409e2,
+        -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1,
+        -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3,
+        -3.223_964_580_411_365e-1,
+        -2.400_758_277_161_838,
+        -2.549_732_539_343_734,
+        4.374_664_141_464_968,
+        2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3,
+        3.224_671_290_700_398e-1,
+        2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    const P_LOW: f64 = 0.024_25;
+
+    // 裾の部分
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.)
+    };
+
+    if p < P_LOW {
+        tail((-2. * p.ln()).sqrt())
+    } else if p > 1. - P_LOW {
+        -tail((-2. * (1. - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.)
+    }
+}
+
+/// 相補誤差関数です．
+/// Numerical Recipesのerfccによる近似で，相対誤差は1.2e-7以下です．
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1. / (1. + 0.5 * z);
+    let r = t * (-z * z - 1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77)))))))))
+        .exp();
+
+    if x >= 0. {
+        r
+    } else {
+        2. - r
+    }
+}
+
+/// 値を1つずつ追加しながら，平均と分散を計算するための構造体です．
+/// 桁落ちを避けるためにWelfordの方法を使っています．
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Moments {
+    pub count: u64,
+    mean: f64,
+    m2: f64,
+}
+
+impl Moments {
+    pub fn push(&mut self, x: f64) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// 不偏分散です．
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            0.
+        } else {
+            self.m2 / (self.count - 1) as f64
+        }
+    }
+
+    /// 平均値の標準誤差です．
+    pub fn std_error(&self) -> f64 {
+        if self.count == 0 {
+            0.
+        } else {
+            (self.variance() / self.count as f64).sqrt()
+        }
+    }
+}
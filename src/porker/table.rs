@@ -0,0 +1,107 @@
+//! 1つのデッキから複数人に手札を配るモジュールです．
+//! 毎回デッキを混ぜて，全員に重ならないように5枚ずつ配るので，
+//! 他の人に配られたカードが自分の手札に来なくなる影響も再現できます．
+
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+use super::*;
+
+/// 1つのテーブルに座れる人数の下限です．
+pub const MIN_PLAYERS: usize = 2;
+
+/// 1つのテーブルに座れる人数の上限です．
+pub const MAX_PLAYERS: usize = 10;
+
+/// 席1つ分の結果です．
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeatResult {
+    pub role_count: RoleCounts,
+    pub sum_score: i64,
+    /// 1人だけで一番強い手札だった回数
+    pub wins: u32,
+    /// 他の人と同じ強さで一番強い手札だった回数
+    pub ties: u32,
+}
+
+/// テーブル全体の結果です．
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub loop_num: u32,
+    pub seed: u64,
+    pub seats: Vec<SeatResult>,
+}
+
+/// 使うカードの中から，players人に重ならないように手札を配り，指定回数対戦します．
+/// 使うカードの重複は取り除き，全員に配れるだけの枚数(5 * players)がない場合はエラーになります．
+/// スコアはスコアシートで計算します．シードを省略した場合はランダムに決め，結果に含めて返します．
+pub fn deal_table(
+    use_cards: &[u32],
+    players: usize,
+    num: u32,
+    seed: Option<u64>,
+    sheet: &ScoreSheet,
+) -> PorkerResult<Table> {
+    if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&players) {
+        return Err(anyhow!(
+            "Error: players must be between {} and {}",
+            MIN_PLAYERS,
+            MAX_PLAYERS
+        ));
+    }
+
+    let mut deck = use_cards.to_vec();
+    deck.sort_unstable();
+    deck.dedup();
+    if deck.len() < players * 5 {
+        return Err(anyhow!(
+            "Error: {} players need at least {} distinct cards",
+            players,
+            players * 5
+        ));
+    }
+
+    let loop_num = limit_loop_num(num);
+    let seed = resolve_seed(seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut seats = vec![
+        SeatResult {
+            role_count: RoleCounts::new(),
+            sum_score: 0,
+            wins: 0,
+            ties: 0,
+        };
+        players
+    ];
+
+    for _ in 0..loop_num {
+        // 先頭から5枚ずつ，席の順に配る
+        let (dealt, _) = deck.partial_shuffle(&mut rng, players * 5);
+        let hands: Vec<[Card; 5]> = dealt
+            .chunks_exact(5)
+            .map(|hand| make_cards_from_id(hand.try_into().unwrap()))
+            .collect();
+        let ranks: Vec<HandRank> = hands.iter().map(rank_hand).collect();
+
+        let best = ranks.iter().max().unwrap();
+        let best_count = ranks.iter().filter(|x| *x == best).count();
+
+        for ((seat, rank), cards) in seats.iter_mut().zip(ranks.iter()).zip(hands.iter()) {
+            seat.role_count.increment(rank.role);
+            seat.sum_score += sheet.score_hand(cards, rank.role);
+            if rank == best {
+                if best_count == 1 {
+                    seat.wins += 1;
+                } else {
+                    seat.ties += 1;
+                }
+            }
+        }
+    }
+
+    Ok(Table {
+        loop_num,
+        seed,
+        seats,
+    })
+}
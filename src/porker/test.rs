@@ -1,131 +1,658 @@
 use super::*;
 
 fn create_test_cards(ids: [u32; 5]) -> [Card; 5] {
-    let mut cards = [Card {
-        id: 0,
-        suit: 0,
-        rank: 0,
-    }; 5];
+    make_cards_from_id(&ids)
+}
 
-    cards[0] = Card::new(ids[0]);
-    cards[1] = Card::new(ids[1]);
-    cards[2] = Card::new(ids[2]);
-    cards[3] = Card::new(ids[3]);
-    cards[4] = Card::new(ids[4]);
+/// どの役でもpoints点になるスコアシートを作ります．
+fn flat_sheet(points: i64) -> ScoreSheet {
+    ScoreSheet {
+        role_scores: Role::ALL.iter().map(|role| (*role, points)).collect(),
+        ..Default::default()
+    }
+}
 
-    cards
+/// スペード13枚とハートのAから7までの，20枚のデッキです．
+fn test_deck() -> Vec<u32> {
+    (0..20).collect()
 }
 
 #[test]
 fn royalflush() {
     let cards = create_test_cards([0, 9, 10, 11, 12]);
-    assert!(is_royalflush(&cards));
+    assert_eq!(classify(&cards), Role::RoyalStraightFlush);
     let cards = create_test_cards([0, 9 + 13, 10, 11, 12]);
-    assert!(!is_royalflush(&cards));
+    assert_ne!(classify(&cards), Role::RoyalStraightFlush);
     let cards = create_test_cards([1, 9, 10, 11, 12]);
-    assert!(!is_royalflush(&cards));
+    assert_ne!(classify(&cards), Role::RoyalStraightFlush);
     let cards = create_test_cards([0, 2, 10, 11, 13]);
-    assert!(!is_royalflush(&cards));
+    assert_ne!(classify(&cards), Role::RoyalStraightFlush);
     let cards = create_test_cards([1, 9 + 14, 10, 11, 13]);
-    assert!(!is_royalflush(&cards));
+    assert_ne!(classify(&cards), Role::RoyalStraightFlush);
 }
 
 #[test]
 fn straitflush() {
-    let mut cards = create_test_cards([0, 1, 2, 3, 4]);
-    assert!(is_straitflush(&mut cards));
-    let mut cards = create_test_cards([8, 9, 10, 11, 12]);
-    assert!(is_straitflush(&mut cards));
-    let mut cards = create_test_cards([3 + 13, 4 + 13, 5 + 13, 6 + 13, 7 + 13]);
-    assert!(is_straitflush(&mut cards));
-    let mut cards = create_test_cards([0, 2, 5, 8, 12]);
-    assert!(!is_straitflush(&mut cards));
-    let mut cards = create_test_cards([13, 2 + 13, 5 + 13, 8 + 13, 12 + 13]);
-    assert!(!is_straitflush(&mut cards));
-    let mut cards = create_test_cards([0, 2, 3, 4, 5]);
-    assert!(!is_straitflush(&mut cards));
-    let mut cards = create_test_cards([0, 14, 2, 3, 4]);
-    assert!(!is_straitflush(&mut cards));
+    let cards = create_test_cards([0, 1, 2, 3, 4]);
+    assert_eq!(classify(&cards), Role::StraightFlush);
+    let cards = create_test_cards([8, 9, 10, 11, 12]);
+    assert_eq!(classify(&cards), Role::StraightFlush);
+    let cards = create_test_cards([3 + 13, 4 + 13, 5 + 13, 6 + 13, 7 + 13]);
+    assert_eq!(classify(&cards), Role::StraightFlush);
+    let cards = create_test_cards([0, 2, 5, 8, 12]);
+    assert_ne!(classify(&cards), Role::StraightFlush);
+    let cards = create_test_cards([13, 2 + 13, 5 + 13, 8 + 13, 12 + 13]);
+    assert_ne!(classify(&cards), Role::StraightFlush);
+    let cards = create_test_cards([0, 2, 3, 4, 5]);
+    assert_ne!(classify(&cards), Role::StraightFlush);
+    let cards = create_test_cards([0, 14, 2, 3, 4]);
+    assert_ne!(classify(&cards), Role::StraightFlush);
+    // ロイヤルストレートフラッシュはストレートフラッシュではない
+    let cards = create_test_cards([0, 9, 10, 11, 12]);
+    assert_ne!(classify(&cards), Role::StraightFlush);
 }
 
 #[test]
 fn fourair() {
     let cards = create_test_cards([0, 13, 26, 39, 4]);
-    assert!(is_fourpair(&cards));
+    assert_eq!(classify(&cards), Role::FourCard);
     let cards = create_test_cards([5, 13 + 5, 26 + 5, 39 + 5, 4]);
-    assert!(is_fourpair(&cards));
+    assert_eq!(classify(&cards), Role::FourCard);
     let cards = create_test_cards([9, 13 + 3, 26 + 3, 39 + 3, 3]);
-    assert!(is_fourpair(&cards));
+    assert_eq!(classify(&cards), Role::FourCard);
     let cards = create_test_cards([0, 13, 25, 39, 4]);
-    assert!(!is_fourpair(&cards));
-    let cards = create_test_cards([0, 13, 25, 39, 4]);
-    assert!(!is_fourpair(&cards));
+    assert_ne!(classify(&cards), Role::FourCard);
 }
 
 #[test]
 fn fulhouse() {
-    let mut cards = create_test_cards([0, 13, 26, 4 + 13, 4]);
-    assert!(is_fulhouse(&mut cards));
-    let mut cards = create_test_cards([0, 13, 4 + 26, 4 + 13, 4]);
-    assert!(is_fulhouse(&mut cards));
-    let mut cards = create_test_cards([0, 13, 25, 4 + 13, 4]);
-    assert!(!is_fulhouse(&mut cards));
+    let cards = create_test_cards([0, 13, 26, 4 + 13, 4]);
+    assert_eq!(classify(&cards), Role::FullHouse);
+    let cards = create_test_cards([0, 13, 4 + 26, 4 + 13, 4]);
+    assert_eq!(classify(&cards), Role::FullHouse);
+    let cards = create_test_cards([0, 13, 25, 4 + 13, 4]);
+    assert_ne!(classify(&cards), Role::FullHouse);
+    // フルハウスはスリーカードではない
+    let cards = create_test_cards([0, 13, 26, 4 + 13, 4]);
+    assert_ne!(classify(&cards), Role::ThreeCard);
 }
 
 #[test]
 fn flush() {
     let cards = create_test_cards([0, 6, 3, 2, 1]);
-    assert!(is_flush(&cards));
+    assert_eq!(classify(&cards), Role::Flush);
     let cards = create_test_cards([0, 14, 3, 2, 1]);
-    assert!(!is_flush(&cards));
+    assert_ne!(classify(&cards), Role::Flush);
     let cards = create_test_cards([0, 6, 3, 2, 14]);
-    assert!(!is_flush(&cards));
+    assert_ne!(classify(&cards), Role::Flush);
     let cards = create_test_cards([14, 6, 3, 2, 14]);
-    assert!(!is_flush(&cards));
+    assert_ne!(classify(&cards), Role::Flush);
+    // ストレートフラッシュはフラッシュではない
+    let cards = create_test_cards([8, 9, 10, 11, 12]);
+    assert_ne!(classify(&cards), Role::Flush);
 }
 
 #[test]
 fn strait() {
-    let mut cards = create_test_cards([0, 1, 2, 3, 4]);
-    assert!(is_strait(&mut cards));
-    let mut cards = create_test_cards([0, 9, 10, 11, 12]);
-    assert!(is_strait(&mut cards));
-    let mut cards = create_test_cards([8, 9, 10, 11, 12]);
-    assert!(is_strait(&mut cards));
-    let mut cards = create_test_cards([0, 1 + 13, 2 + 26, 3 + 39, 4]);
-    assert!(is_strait(&mut cards));
-    let mut cards = create_test_cards([8, 9 + 26, 10, 11 + 13, 12 + 39]);
-    assert!(is_strait(&mut cards));
-    let mut cards = create_test_cards([0, 1, 2, 5 + 13, 4]);
-    assert!(!is_strait(&mut cards));
+    let cards = create_test_cards([0, 1 + 13, 2 + 26, 3 + 39, 4]);
+    assert_eq!(classify(&cards), Role::Straight);
+    let cards = create_test_cards([8, 9 + 26, 10, 11 + 13, 12 + 39]);
+    assert_eq!(classify(&cards), Role::Straight);
+    let cards = create_test_cards([0, 9 + 13, 10, 11, 12]);
+    assert_eq!(classify(&cards), Role::Straight);
+    let cards = create_test_cards([0, 1, 2, 5 + 13, 4]);
+    assert_ne!(classify(&cards), Role::Straight);
+    // J, Q, K, A, 2 のように一周するものはストレートではない
+    let cards = create_test_cards([10, 11 + 13, 12, 0, 1 + 13]);
+    assert_ne!(classify(&cards), Role::Straight);
+    // ストレートフラッシュはストレートではない
+    let cards = create_test_cards([0, 1, 2, 3, 4]);
+    assert_ne!(classify(&cards), Role::Straight);
 }
 
 #[test]
 fn threepair() {
     let cards = create_test_cards([0, 13, 26, 5, 4]);
-    assert!(is_threepair(&cards));
+    assert_eq!(classify(&cards), Role::ThreeCard);
     let cards = create_test_cards([1, 14, 27, 5, 4]);
-    assert!(is_threepair(&cards));
+    assert_eq!(classify(&cards), Role::ThreeCard);
     let cards = create_test_cards([7, 13, 26, 0, 4]);
-    assert!(is_threepair(&cards));
+    assert_eq!(classify(&cards), Role::ThreeCard);
 }
 
 #[test]
 fn twopair() {
     let cards = create_test_cards([0, 13, 1, 14, 4]);
-    assert!(is_twopair(&cards));
+    assert_eq!(classify(&cards), Role::TwoPair);
     let cards = create_test_cards([9, 13, 0, 14, 1]);
-    assert!(is_twopair(&cards));
+    assert_eq!(classify(&cards), Role::TwoPair);
     let cards = create_test_cards([0, 13, 3, 15, 4]);
-    assert!(!is_twopair(&cards));
+    assert_ne!(classify(&cards), Role::TwoPair);
 }
 
 #[test]
 fn onepair() {
     let cards = create_test_cards([0, 13, 2, 6, 4]);
-    assert!(is_onepair(&cards));
+    assert_eq!(classify(&cards), Role::OnePair);
     let cards = create_test_cards([0, 3, 2, 6, 4]);
-    assert!(!is_onepair(&cards));
+    assert_ne!(classify(&cards), Role::OnePair);
     let cards = create_test_cards([0, 7, 2, 6, 4]);
-    assert!(!is_onepair(&cards));
+    assert_ne!(classify(&cards), Role::OnePair);
+}
+
+#[test]
+fn classify_ignores_order() {
+    let ids = [0, 9 + 13, 10, 11 + 26, 12];
+    let expected = classify(&create_test_cards(ids));
+    assert_eq!(expected, Role::Straight);
+
+    for i in 0..5 {
+        let mut rotated = ids;
+        rotated.rotate_left(i);
+        assert_eq!(classify(&create_test_cards(rotated)), expected);
+        rotated.reverse();
+        assert_eq!(classify(&create_test_cards(rotated)), expected);
+    }
+}
+
+#[test]
+fn classify_reason() {
+    let reason = classify_with_reason(&create_test_cards([4, 13, 17, 0, 6]));
+    assert_eq!(reason.role, Role::TwoPair);
+    let ids: Vec<u32> = reason.cards.iter().map(|x| x.id).collect();
+    assert_eq!(ids, vec![0, 13, 4, 17]);
+
+    let reason = classify_with_reason(&create_test_cards([1, 15, 30, 45, 13]));
+    assert_eq!(reason.role, Role::NoPair);
+    assert_eq!(reason.cards, vec![Card::new(13)]);
+}
+
+#[test]
+fn card_notation() {
+    assert_eq!(Card::new(0).to_string(), "As");
+    assert_eq!(Card::new(35).to_string(), "Td");
+    assert_eq!(format!("{:#}", Card::new(51)), "K♣");
+
+    for id in 0..52 {
+        let card = Card::new(id);
+        assert_eq!(card.to_string().parse::<Card>().unwrap(), card);
+        assert_eq!(format!("{:#}", card).parse::<Card>().unwrap(), card);
+    }
+    assert_eq!("10♥".parse::<Card>().unwrap(), Card::new(22));
+    assert_eq!("qH".parse::<Card>().unwrap(), Card::new(24));
+    assert!("1x".parse::<Card>().is_err());
+    assert!("14s".parse::<Card>().is_err());
+    assert!("s".parse::<Card>().is_err());
+}
+
+#[test]
+fn role_counts_json() {
+    let mut role_count = RoleCounts::new();
+    role_count.increment(Role::ThreeCard);
+    role_count.increment(Role::ThreeCard);
+    role_count[Role::RoyalStraightFlush] += 1;
+
+    let json = serde_json::to_value(role_count).unwrap();
+    assert_eq!(json["threepair"], 2);
+    assert_eq!(json["royalflush"], 1);
+    assert_eq!(json["nopair"], 0);
+    assert_eq!(json.as_object().unwrap().len(), Role::COUNT);
+
+    let parsed: RoleCounts = serde_json::from_str(r#"{"threepair": 2, "royalflush": 1}"#).unwrap();
+    assert_eq!(parsed, role_count);
+    assert_eq!(parsed.total(), 3);
+}
+
+#[test]
+fn analysis_removal_and_swap_deltas() {
+    use super::analysis::analyze_cards_with_rng;
+    use super::scoring::ScoreRule;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // 役のスコアは0で，Asが入っていたら100点，Kcが入っていたら300点
+    let sheet = ScoreSheet {
+        rules: vec![
+            ScoreRule::CardPoints {
+                card: Card::new(0),
+                points: 100,
+            },
+            ScoreRule::CardPoints {
+                card: Card::new(51),
+                points: 300,
+            },
+        ],
+        ..flat_sheet(0)
+    };
+    let deck = test_deck();
+    let analysis = analyze_cards_with_rng(&deck, 5000, &sheet, true, &mut StdRng::seed_from_u64(1)).unwrap();
+    let again = analyze_cards_with_rng(&deck, 5000, &sheet, true, &mut StdRng::seed_from_u64(1)).unwrap();
+    assert_eq!(analysis, again);
+
+    let ace = &analysis.cards[0];
+    assert_eq!(ace.id, 0);
+    // Asを抜くと点が入らなくなる
+    assert_eq!(ace.removed_delta, Some(-analysis.mean_score));
+    assert!(ace.removed_delta.unwrap() < 0.);
+
+    // Asの入れ替え先は，Kcなら+200点，それ以外なら-100点
+    let swaps = ace.swaps.as_ref().unwrap();
+    assert_eq!(swaps.len(), 32);
+    assert_eq!(swaps[0].id, 51);
+    assert!(swaps[0].delta > 0.);
+    assert!(swaps[1..].iter().all(|x| x.delta < 0.));
+    let hands_rate = ace.hands as f64 / analysis.loop_num as f64;
+    assert!((swaps[0].delta - 200. * hands_rate).abs() < 1e-9);
+
+    // 他のカードはKcと入れ替えると必ず良くなる
+    let five = &analysis.cards[5];
+    let best = five.swaps.as_ref().unwrap()[0];
+    assert_eq!(best.id, 51);
+    assert!(best.delta > 0.);
+
+    // 入れ替えを分析しない場合は回数の上限も変わらない
+    let plain = analyze_cards_with_rng(&deck, 200_000, &sheet, false, &mut StdRng::seed_from_u64(1)).unwrap();
+    assert_eq!(plain.loop_num, 200_000);
+    assert!(plain.cards.iter().all(|x| x.swaps.is_none()));
+}
+
+#[test]
+fn compare_uses_common_random_numbers() {
+    use super::compare::compare_decks;
+
+    let sheet = ScoreSheet::default();
+    let deck = test_deck();
+    let shifted: Vec<u32> = (20..40).collect();
+
+    // 同じシードなら結果も同じ
+    let first = compare_decks(&[deck.clone(), shifted.clone()], 2000, Some(7), &sheet).unwrap();
+    let second = compare_decks(&[deck.clone(), shifted], 2000, Some(7), &sheet).unwrap();
+    assert_eq!(first, second);
+    assert_eq!(first.seed, 7);
+
+    // 同じデッキ同士は同じ手札が配られるので，差がまったく無い
+    let same = compare_decks(&[deck.clone(), deck], 2000, Some(7), &sheet).unwrap();
+    assert_eq!(same.decks[0], same.decks[1]);
+    let diff = &same.diffs[0];
+    assert_eq!(diff.score_delta, 0);
+    assert!(diff.role_diff.values().all(|x| *x == 0));
+    assert_eq!(diff.std_error, 0.);
+    assert_eq!(diff.z_score, None);
+    assert_eq!(diff.p_value, 1.);
+    assert!(!diff.significant);
+}
+
+#[test]
+fn stats_known_values() {
+    use super::stats::{normal_cdf, normal_quantile, two_sided_p_value};
+
+    // erfcの近似の誤差は1.2e-7程度
+    assert!((normal_cdf(0.) - 0.5).abs() < 1e-6);
+    assert!((normal_cdf(1.) - 0.841_344_746).abs() < 1e-6);
+    assert!((normal_cdf(-1.96) - 0.024_997_895).abs() < 1e-6);
+    assert!((two_sided_p_value(0.) - 1.).abs() < 1e-6);
+    assert!((two_sided_p_value(1.96) - 0.049_995_790).abs() < 1e-6);
+    assert_eq!(two_sided_p_value(-2.5), two_sided_p_value(2.5));
+    assert!((normal_quantile(0.975) - 1.959_963_985).abs() < 1e-6);
+    assert!((normal_cdf(normal_quantile(0.1)) - 0.1).abs() < 1e-6);
+}
+
+#[test]
+fn hand_rank_tiebreak() {
+    // Aのワンペアは2のワンペアより強い
+    let aces = rank_hand(&create_test_cards([0, 13, 4, 6, 8]));
+    let twos = rank_hand(&create_test_cards([1, 14, 4, 6, 12]));
+    assert_eq!(aces.kickers, vec![14, 9, 7, 5]);
+    assert!(aces > twos);
+    assert!(aces.value() > twos.value());
+
+    // A,2,3,4,5のストレートは一番弱いストレート
+    let wheel = rank_hand(&create_test_cards([0, 14, 2, 3, 4 + 13]));
+    let six_high = rank_hand(&create_test_cards([1, 15, 3, 4, 5 + 13]));
+    assert_eq!(wheel.kickers, vec![5]);
+    assert!(wheel < six_high);
+
+    assert!(evaluate_hands(&[vec![0, 0, 1, 2, 3]], &ScoreSheet::default()).is_err());
+    assert!(evaluate_hands(&[vec![0, 1, 2, 3]], &ScoreSheet::default()).is_err());
+}
+
+#[test]
+fn showdown_counts() {
+    let full: Vec<u32> = Card::all_cards_id();
+    // A，K，Qが4枚ずつしかないデッキは，必ずツーペア以上になる
+    let strong = vec![0, 13, 26, 39, 12, 25, 38, 51, 11, 24, 37, 50];
+    let result = showdown::showdown(&[full, strong], 1000, Some(7), &ScoreSheet::default()).unwrap();
+    assert_eq!(result.sides[0].wins + result.sides[1].wins + result.ties, 1000);
+    assert_eq!(result.sides[1].role_count[Role::NoPair] + result.sides[1].role_count[Role::OnePair], 0);
+    assert!(result.sides[1].wins > result.sides[0].wins);
+}
+
+#[test]
+fn table_deals_distinct_hands() {
+    let result = table::deal_table(&Card::all_cards_id(), 4, 1000, Some(3), &ScoreSheet::default()).unwrap();
+    assert_eq!(result.seats.len(), 4);
+    for seat in result.seats.iter() {
+        assert_eq!(seat.role_count.total(), 1000);
+    }
+    let best: u32 = result.seats.iter().map(|x| x.wins).sum();
+    assert!(best <= 1000);
+
+    // 10人に配るには50枚必要
+    assert!(table::deal_table(&(0..49).collect::<Vec<u32>>(), 10, 10, None, &ScoreSheet::default()).is_err());
+    assert!(table::deal_table(&Card::all_cards_id(), 11, 10, None, &ScoreSheet::default()).is_err());
+}
+
+#[test]
+fn challenge_date_and_rules() {
+    let date: challenge::Date = "2023-03-12".parse().unwrap();
+    assert_eq!(date.to_string(), "2023-03-12");
+    assert!("2023-02-30".parse::<challenge::Date>().is_err());
+    assert!("2023/03/12".parse::<challenge::Date>().is_err());
+    assert!("2024-02-29".parse::<challenge::Date>().is_ok());
+
+    // 同じ日付なら同じルールとシード，同じデッキなら同じ結果になる
+    let challenge = challenge::Challenge::new(date);
+    assert_eq!(challenge, challenge::Challenge::new("2023-03-12".parse().unwrap()));
+    assert_ne!(challenge.seed, challenge::Challenge::new("2023-03-13".parse().unwrap()).seed);
+
+    let constraints = &challenge.rules.constraints;
+    let deck: Vec<u32> = (0..52)
+        .filter(|id| !constraints.banned_ranks.contains(&Card::new(*id).rank))
+        .take(constraints.min_size.unwrap())
+        .collect();
+    assert_eq!(challenge.score(&deck).unwrap(), challenge.score(&deck).unwrap());
+    assert!(challenge.score(&deck[1..]).is_err());
+}
+
+#[test]
+fn deck_constraints_report_all_violations() {
+    let constraints: constraints::DeckConstraints = serde_json::from_str(
+        r#"{"maxSize": 6, "maxPerSuit": 3, "distinct": true, "bannedCards": ["Ks"],
+            "budget": 10, "rankCosts": {"1": 5}}"#,
+    )
+    .unwrap();
+    assert_eq!(constraints.default_cost, 1);
+
+    // コストは As(5) + 2s + 3s + Ks + Ah(5) * 3 = 23
+    let violations = constraints.check(&[0, 1, 2, 12, 13, 13, 13]);
+    let rules: Vec<&str> = violations.iter().map(|x| x.rule).collect();
+    assert_eq!(rules, vec!["maxSize", "maxPerSuit", "distinct", "bannedCards", "budget"]);
+    assert_eq!(violations[1].cards, vec![0, 1, 2, 12]);
+    assert_eq!(violations[2].cards, vec![13]);
+
+    assert!(constraints.validate(&[0, 1, 2, 14]).is_ok());
+    let error = constraints.validate(&[12]).unwrap_err();
+    assert!(error.downcast_ref::<constraints::ConstraintError>().is_some());
+}
+
+#[test]
+fn score_sheet_rules() {
+    let sheet: ScoreSheet = serde_json::from_str(
+        r#"{"roleScores": {"nopair": 0},
+            "rules": [
+              {"type": "suitMultiplier", "role": "flush", "suit": "heart", "multiplier": 2},
+              {"type": "cardPoints", "card": "Qs", "points": -50},
+              {"type": "rankRole", "role": "onepair", "rank": 1, "scoreAs": "threepair"}
+            ]}"#,
+    )
+    .unwrap();
+
+    let score = |ids: [u32; 5]| {
+        let cards = create_test_cards(ids);
+        sheet.score_hand(&cards, classify(&cards))
+    };
+    // ハートのフラッシュは2倍，スペードのフラッシュはそのまま
+    assert_eq!(score([13, 15, 17, 19, 21]), 400);
+    assert_eq!(score([0, 2, 4, 6, 8]), 200);
+    // Aのワンペアはスリーカードのスコア，2のワンペアはそのまま
+    assert_eq!(score([0, 13, 2, 4, 6]), 20);
+    assert_eq!(score([1, 14, 2, 4, 6]), 5);
+    // スペードのQが入っていると-50点
+    assert_eq!(score([11, 14, 28, 43, 6]), -50);
+
+    // ルールが無ければrole_scoreと同じ
+    let cards = create_test_cards([0, 13, 2, 4, 6]);
+    assert_eq!(ScoreSheet::default().score_hand(&cards, Role::OnePair), 5);
+}
+
+#[test]
+fn score_sheet_rejects_overflowing_rules() {
+    let sheet = |json: &str| serde_json::from_str::<ScoreSheet>(json).unwrap();
+    assert!(ScoreSheet::default().validate().is_ok());
+    assert!(sheet(r#"{"rules": [{"type": "suitMultiplier", "suit": "heart", "multiplier": -1000}]}"#)
+        .validate()
+        .is_ok());
+
+    for json in [
+        r#"{"roleScores": {"flush": 1000000001}}"#,
+        r#"{"roleScores": {"nopair": -9223372036854775808}}"#,
+        r#"{"rules": [{"type": "cardPoints", "card": "Qs", "points": 9223372036854775807}]}"#,
+        r#"{"rules": [
+              {"type": "suitMultiplier", "suit": "heart", "multiplier": 1000000},
+              {"type": "suitMultiplier", "suit": "heart", "multiplier": 1000000},
+              {"type": "suitMultiplier", "suit": "heart", "multiplier": 1000000}
+            ]}"#,
+    ] {
+        let error = sheet(json).validate().unwrap_err();
+        assert!(error.to_string().starts_with("Error: Score sheet can exceed"), "{}", error);
+    }
+}
+
+#[test]
+fn score_sheet_applies_to_every_simulator() {
+    use super::{analysis::analyze_cards, compare::compare_decks, showdown::showdown, table::deal_table};
+
+    // どの役も100点のスコアシートなら，合計スコアは必ず回数の100倍になる
+    let sheet = flat_sheet(100);
+    let deck = test_deck();
+    let other: Vec<u32> = (20..40).collect();
+
+    let analysis = analyze_cards(&deck, 500, &sheet, false).unwrap();
+    assert_eq!(analysis.sum_score, 50_000);
+    assert!(analysis.cards.iter().all(|x| x.mean_score == 100.));
+    assert_ne!(analyze_cards(&deck, 500, &ScoreSheet::default(), false).unwrap().sum_score, 50_000);
+
+    let comparison = compare_decks(&[deck.clone(), other.clone()], 500, Some(1), &sheet).unwrap();
+    assert!(comparison.decks.iter().all(|x| x.sum_score == 50_000));
+    assert_eq!(comparison.diffs[0].score_delta, 0);
+
+    let result = showdown(&[deck, other], 500, Some(1), &sheet).unwrap();
+    assert!(result.sides.iter().all(|x| x.sum_score == 50_000));
+
+    let table = deal_table(&Card::all_cards_id(), 3, 500, Some(1), &sheet).unwrap();
+    assert!(table.seats.iter().all(|x| x.sum_score == 50_000));
+}
+
+#[test]
+fn distribution_percentiles() {
+    use super::distribution::DistributionBuilder;
+
+    let mut builder = DistributionBuilder::new(3);
+    for score in [1, 1, 5, 1, 20, 5, 1, 1] {
+        builder.push(score);
+    }
+    let distribution = builder.build();
+
+    let bins: Vec<(i64, u64)> = distribution.histogram.iter().map(|x| (x.score, x.count)).collect();
+    assert_eq!(bins, vec![(1, 5), (5, 2), (20, 1)]);
+    assert_eq!(distribution.mean, 35. / 8.);
+    let percentiles = distribution.percentiles.unwrap();
+    assert_eq!((percentiles.p5, percentiles.p50, percentiles.p95), (1, 1, 20));
+
+    // 最後の2つは半端なので，バッチは[7, 26]の2つ
+    assert_eq!(distribution.batches.count, 2);
+    assert_eq!(distribution.batches.mean, 16.5);
+    let percentiles = distribution.batches.percentiles.unwrap();
+    assert_eq!((percentiles.p5, percentiles.p50, percentiles.p95), (7, 7, 26));
+}
+
+#[test]
+fn sessions_track_bankroll() {
+    use super::session::{simulate_sessions, SessionConfig};
+
+    // どの役でも100点にして，セッションの合計が決まるようにする
+    let sheet = flat_sheet(100);
+    let use_cards = test_deck();
+    let mut config = SessionConfig {
+        hands: 10,
+        sessions: 20,
+        ante: 30,
+        bankroll: None,
+        seed: Some(1),
+    };
+    let summary = simulate_sessions(&use_cards, &config, &sheet).unwrap();
+    assert_eq!(summary.percentiles.p50, 700);
+    assert_eq!(summary.best.total, summary.worst.total);
+    assert_eq!(summary.best.role_count.total(), 10);
+    assert_eq!(summary.risk_of_ruin, None);
+
+    // 持ち点20では最初の参加費30が払えない
+    config.bankroll = Some(20);
+    let summary = simulate_sessions(&use_cards, &config, &sheet).unwrap();
+    assert_eq!(summary.risk_of_ruin, Some(1.));
+    assert_eq!(summary.best.hands, 0);
+
+    config.bankroll = Some(30);
+    let summary = simulate_sessions(&use_cards, &config, &sheet).unwrap();
+    assert_eq!(summary.risk_of_ruin, Some(0.));
+
+    config.sessions = MAX_LOOP_NUM;
+    assert!(simulate_sessions(&use_cards, &config, &sheet).is_err());
+}
+
+#[test]
+fn convergence_stops_at_precision() {
+    use super::convergence::converge_porker;
+    use super::stats::normal_quantile;
+
+    assert!((normal_quantile(0.975) - 1.959_964).abs() < 1e-6);
+    assert!((normal_quantile(0.01) + 2.326_348).abs() < 1e-6);
+    assert_eq!(normal_quantile(0.5), 0.);
+
+    // どの役でも同じスコアなら分散が0なので，最初の確認で止まる
+    let sheet = flat_sheet(10);
+    let use_cards = test_deck();
+    let (_, sum_score, loop_num, convergence) = converge_porker(&use_cards, 0.005, 0.95, 100_000, &sheet).unwrap();
+    assert_eq!(loop_num, 1000);
+    assert_eq!(sum_score, 10_000);
+    assert!(convergence.converged);
+    assert_eq!(convergence.achieved, Some(0.));
+
+    // 届かない精度なら最大回数で止まる
+    let (_, _, loop_num, convergence) =
+        converge_porker(&use_cards, 1e-9, 0.95, 2500, &ScoreSheet::default()).unwrap();
+    assert_eq!(loop_num, 2500);
+    assert!(!convergence.converged);
+
+    assert!(converge_porker(&use_cards, 0.01, 1., 1000, &sheet).is_err());
+}
+
+#[test]
+fn variance_reduction_is_exact_on_rare_roles() {
+    use super::sampling::{estimate_porker, Strategy};
+
+    // スペードとハートのA~5だけのデッキでは，ストレートフラッシュは252通り中2通り
+    let use_cards = [0, 1, 2, 3, 4, 13, 14, 15, 16, 17];
+    let exact = 2. / 252.;
+    let sheet = ScoreSheet::default();
+
+    let estimate = estimate_porker(&use_cards, 20_000, Strategy::Stratified, Some(7), &sheet).unwrap();
+    assert!((estimate.probabilities[&Role::StraightFlush] - exact).abs() < 1e-12);
+    assert!((estimate.probabilities.values().sum::<f64>() - 1.).abs() < 1e-9);
+    assert!(estimate.loop_num <= 20_000);
+
+    let estimate = estimate_porker(&use_cards, 20_000, Strategy::ControlVariate, Some(7), &sheet).unwrap();
+    assert!((estimate.probabilities[&Role::StraightFlush] - exact).abs() < 1e-12);
+    assert!(estimate.effective_sample_size > 18_000.);
+
+    let estimate = estimate_porker(&use_cards, 20_000, Strategy::Plain, Some(7), &sheet).unwrap();
+    assert_eq!(estimate.effective_sample_size, 20_000.);
+    assert!((estimate.probabilities[&Role::StraightFlush] - exact).abs() < 0.005);
+
+    assert!(estimate_porker(&use_cards, 10, Strategy::Stratified, None, &sheet).is_err());
+}
+
+#[test]
+fn parse_deck_accepts_ids_and_notation() {
+    assert_eq!(parse_deck("0, Kd 12\nTs").unwrap(), vec![0, 38, 12, 9]);
+    assert_eq!(parse_deck(r#"[0, "As", {"id": 51}]"#).unwrap(), vec![0, 0, 51]);
+    assert!(parse_deck("52").is_err());
+    assert!(parse_deck("Zz").is_err());
+}
+
+#[test]
+fn batch_runs_each_deck() {
+    use super::batch::{parse_batch, run_batch, write_batch, BatchFormat};
+
+    let csv = "id,useCards,num,seed\n\
+               a,\"0,1,2,3,4,5,6,7,8,9\",1000,1\n\
+               \n\
+               b,As 2s 3s 4s 5s 6s 7s 8s 9s Ts,1000,1\n\
+               c,\"0,1\",1000,1\n\
+               d,0 1 2 3 4 5,x,1\n";
+    assert_eq!(BatchFormat::detect(csv), BatchFormat::Csv);
+    let rows = parse_batch(csv, BatchFormat::Csv).unwrap();
+    assert_eq!(rows.iter().map(|(line, _)| *line).collect::<Vec<_>>(), vec![2, 4, 5, 6]);
+
+    let results = run_batch(&rows, &ScoreSheet::default(), &Default::default(), 3);
+    // 同じカードを同じシードで実行するので，同じ結果になる
+    assert_eq!(results[0].result, results[1].result);
+    assert_eq!(results[0].number, Some(1000));
+    let (low, high) = (results[0].ciLow.unwrap(), results[0].ciHigh.unwrap());
+    assert!(low < results[0].meanScore.unwrap() && results[0].meanScore.unwrap() < high);
+    // カードが足りない行と，読み込めない行はその行だけエラーになる
+    assert!(results[2].error.is_some() && results[2].result.is_none());
+    assert!(results[3].error.as_deref().unwrap().contains("num"));
+
+    let out = write_batch(&results, BatchFormat::Jsonl);
+    assert_eq!(out.lines().count(), 4);
+    let jsonl = parse_batch(r#"{"id": "a", "useCards": [0, "2s", 2, 3, 4, 5]}"#, BatchFormat::Jsonl).unwrap();
+    assert_eq!(jsonl[0].1.as_ref().unwrap().useCards, vec![0, 1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn role_name_matches_serde() {
+    for role in Role::ALL {
+        assert_eq!(serde_json::to_value(role).unwrap(), role.name());
+    }
+}
+
+#[test]
+fn batch_reports_bad_rows_without_panicking() {
+    use super::batch::{parse_batch, run_batch, BatchFormat};
+
+    let jsonl = r#"{"id": "ok1", "useCards": [0, 1, 2, 3, 4, 5, 6, 7, 8, 9], "num": 100, "seed": 1}
+{"id": "empty", "useCards": []}
+{"id": "few", "useCards": [0, 0, 1, 2, 3], "num": 100}
+{"id": "ok2", "useCards": [10, 11, 12, 13, 14, 15, 16, 17, 18, 19], "num": 100, "seed": 2}"#;
+    let rows = parse_batch(jsonl, BatchFormat::Jsonl).unwrap();
+    let results = run_batch(&rows, &ScoreSheet::default(), &Default::default(), 2);
+
+    assert_eq!(results.len(), 4);
+    assert_eq!(results.iter().map(|x| x.line).collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+    assert!(results[0].error.is_none() && results[3].error.is_none());
+    assert_eq!(results[3].number, Some(100));
+    for bad in &results[1..3] {
+        assert!(bad.result.is_none());
+        assert!(bad.error.as_deref().unwrap().contains("at least 5 distinct cards"));
+    }
+    assert_eq!(results[1].id.as_deref(), Some("empty"));
+}
+
+#[test]
+fn handout_deals_from_duplicate_heavy_decks() {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // Asが100枚と他のカードが4枚のデッキは，20回引いても5枚揃わないことが多い
+    let mut deck = vec![0; 100];
+    deck.extend([1, 2, 3, 4]);
+    assert!(check_deck(&deck).is_ok());
+    let mut rng = StdRng::seed_from_u64(1);
+    for _ in 0..100 {
+        let mut hand = handout_cards_with_rng(&deck, &mut rng).unwrap();
+        hand.sort_unstable();
+        assert_eq!(hand, [0, 1, 2, 3, 4]);
+    }
+
+    assert!(check_deck(&[0, 0, 1, 2, 3]).is_err());
+    assert!(handout_cards_with_rng(&[0, 0, 1, 2, 3], &mut rng).is_err());
 }
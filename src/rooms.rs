@@ -0,0 +1,473 @@
+//! WebSocketで遊ぶ対戦ルームのモジュールです．
+//! プレイヤーはルームを作るか参加して，デッキ(使うカード)を提出します．
+//! 全員が提出するか，最初の提出から制限時間が過ぎると，提出したプレイヤーのデッキからそれぞれ手札を配って役を判定し，
+//! 結果をルームの全員に送ってデータベースに保存します．
+//! ルームの状態はメモリ上にだけ保持しているので，サーバーを再起動すると消えます．
+//!
+//! メッセージはどちらの向きもJSONのテキストで，typeで種類を表します．
+//! - クライアントから: create(name)，join(room, name)，deck(useCards)，leave
+//! - サーバーから: joined(room, playerId)，room(ルームの状態)，result(対戦結果)，error(message, violations)
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_ws::{Message, MessageStream, Session};
+use anyhow::anyhow;
+use rand::{seq::SliceRandom, thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::database::{self, PlayerHand};
+use crate::porker::constraints::{ConstraintError, DeckConstraints, Violation};
+use crate::porker::{self, ScoreSheet};
+
+/// 1つのルームに参加できる人数の上限です．
+pub const MAX_ROOM_PLAYERS: usize = porker::table::MAX_PLAYERS;
+
+/// 最初のデッキが提出されてから，手札を配るまでの制限時間(秒)です．
+pub const TURN_SECONDS: u64 = 30;
+
+/// ルームのコードに使う文字です．読み間違えやすい0，O，1，Iは使いません．
+const ROOM_CODE_CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+const ROOM_CODE_LEN: usize = 6;
+
+/// クライアントから送られるメッセージです．
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+#[allow(non_snake_case)]
+enum ClientMessage {
+    Create {
+        name: String,
+    },
+    Join {
+        room: String,
+        name: String,
+    },
+    Deck {
+        #[serde(deserialize_with = "crate::deserialize_card_ids")]
+        useCards: Vec<u32>,
+    },
+    Leave,
+}
+
+/// サーバーから送るメッセージです．
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+#[allow(non_snake_case)]
+enum ServerMessage<'a> {
+    Joined {
+        room: &'a str,
+        playerId: u64,
+    },
+    Room {
+        room: &'a str,
+        round: u32,
+        remainingSeconds: Option<u64>,
+        players: Vec<PlayerView<'a>>,
+    },
+    Result {
+        room: &'a str,
+        round: u32,
+        matchId: Option<i64>,
+        hands: &'a [PlayerHand],
+    },
+    /// violationsは，提出したデッキが制約を破っている場合に破っているルールの一覧(それ以外は空)です．
+    Error {
+        message: String,
+        violations: &'a [Violation],
+    },
+}
+
+impl ServerMessage<'_> {
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// エラーをerrorメッセージのJSONにします．
+/// デッキの制約を破っている場合は，破っているルールの一覧も入れます．
+pub(crate) fn error_message(e: &anyhow::Error) -> String {
+    let message = match e.downcast_ref::<ConstraintError>() {
+        Some(error) => ServerMessage::Error {
+            message: "Error: useCards breaks deck constraints".to_string(),
+            violations: &error.violations,
+        },
+        None => ServerMessage::Error {
+            message: e.to_string(),
+            violations: &[],
+        },
+    };
+    message.to_json()
+}
+
+/// ルームの状態で送る，プレイヤー1人分の情報です．readyはデッキを提出済みかどうかです．
+#[derive(Serialize)]
+struct PlayerView<'a> {
+    id: u64,
+    name: &'a str,
+    ready: bool,
+}
+
+struct Player {
+    id: u64,
+    name: String,
+    session: Session,
+    deck: Option<Vec<u32>>,
+}
+
+struct Room {
+    players: Vec<Player>,
+    round: u32,
+    /// 手札を配る期限です．まだ誰もデッキを提出していなければNone
+    deadline: Option<Instant>,
+}
+
+impl Room {
+    /// ルームの状態のメッセージと，送り先のセッションを返します．
+    fn state(&self, code: &str) -> (Vec<Session>, String) {
+        let message = ServerMessage::Room {
+            room: code,
+            round: self.round,
+            remainingSeconds: self
+                .deadline
+                .map(|x| x.saturating_duration_since(Instant::now()).as_secs()),
+            players: self
+                .players
+                .iter()
+                .map(|x| PlayerView {
+                    id: x.id,
+                    name: &x.name,
+                    ready: x.deck.is_some(),
+                })
+                .collect(),
+        };
+        (self.sessions(), message.to_json())
+    }
+
+    fn sessions(&self) -> Vec<Session> {
+        self.players.iter().map(|x| x.session.clone()).collect()
+    }
+
+    /// デッキを提出したプレイヤーに手札を配って判定し，スコアシートでスコアを計算します．
+    /// 提出したプレイヤーが2人未満の場合はNoneを返します．
+    fn deal(&self, sheet: &ScoreSheet) -> Option<Vec<PlayerHand>> {
+        let mut rng = thread_rng();
+        let mut dealt = Vec::new();
+        for player in self.players.iter() {
+            if let Some(deck) = &player.deck {
+                let mut cards_id = [0; 5];
+                for (i, id) in deck.choose_multiple(&mut rng, 5).enumerate() {
+                    cards_id[i] = *id;
+                }
+                let cards = porker::make_cards_from_id(&cards_id);
+                let rank = porker::rank_hand(&cards);
+                let score = sheet.score_hand(&cards, rank.role);
+                dealt.push((player, cards_id, rank, score));
+            }
+        }
+        if dealt.len() < 2 {
+            return None;
+        }
+
+        let best = dealt.iter().map(|x| &x.2).max().cloned();
+        Some(
+            dealt
+                .into_iter()
+                .map(|(player, cards_id, rank, score)| PlayerHand {
+                    playerId: player.id,
+                    name: player.name.clone(),
+                    cards: cards_id,
+                    notation: cards_id.iter().map(|id| porker::Card::new(*id).to_string()).collect(),
+                    role: rank.role,
+                    score,
+                    winner: Some(&rank) == best.as_ref(),
+                })
+                .collect(),
+        )
+    }
+}
+
+/// デッキを提出した後にすることです．
+enum Next {
+    Wait,
+    StartTimer(u32),
+    Play(u32),
+}
+
+/// すべてのルームと，対戦結果を保存するデータベース，スコアの計算に使うスコアシート，
+/// 提出されたデッキを確認するデッキの制約を管理する構造体です．
+pub struct Hub {
+    rooms: Mutex<HashMap<String, Room>>,
+    next_player_id: AtomicU64,
+    pool: SqlitePool,
+    sheet: ScoreSheet,
+    constraints: DeckConstraints,
+}
+
+impl Hub {
+    pub fn new(pool: SqlitePool, sheet: ScoreSheet, constraints: DeckConstraints) -> Hub {
+        Hub {
+            rooms: Mutex::new(HashMap::new()),
+            next_player_id: AtomicU64::new(1),
+            pool,
+            sheet,
+            constraints,
+        }
+    }
+
+    /// 1つのメッセージを処理します．roomには，このプレイヤーが参加しているルームのコードを保持します．
+    async fn handle(
+        self: &Arc<Self>,
+        player_id: u64,
+        session: &Session,
+        room: &mut Option<String>,
+        message: ClientMessage,
+    ) -> anyhow::Result<()> {
+        match message {
+            ClientMessage::Create { name } => {
+                self.leave_room(room, player_id).await;
+                let code = self.create(player_id, name, session.clone());
+                self.joined(room, code, player_id, session).await;
+            }
+            ClientMessage::Join { room: code, name } => {
+                self.leave_room(room, player_id).await;
+                let code = code.to_uppercase();
+                self.join(&code, player_id, name, session.clone())?;
+                self.joined(room, code, player_id, session).await;
+            }
+            ClientMessage::Deck { useCards } => {
+                let code = room.clone().ok_or_else(|| anyhow!("Error: Not in a room"))?;
+                match self.submit_deck(&code, player_id, useCards)? {
+                    Next::Wait => {}
+                    Next::StartTimer(round) => {
+                        let hub = self.clone();
+                        let timer_code = code.clone();
+                        actix_web::rt::spawn(async move {
+                            actix_web::rt::time::sleep(Duration::from_secs(TURN_SECONDS)).await;
+                            hub.play_round(&timer_code, round).await;
+                        });
+                    }
+                    Next::Play(round) => {
+                        self.play_round(&code, round).await;
+                        return Ok(());
+                    }
+                }
+                self.broadcast_state(&code).await;
+            }
+            ClientMessage::Leave => self.leave_room(room, player_id).await,
+        }
+        Ok(())
+    }
+
+    /// 新しいルームを作って参加し，ルームのコードを返します．
+    fn create(&self, player_id: u64, name: String, session: Session) -> String {
+        let mut rooms = self.rooms.lock().unwrap();
+        let mut rng = thread_rng();
+        let code = loop {
+            let code: String = (0..ROOM_CODE_LEN)
+                .map(|_| ROOM_CODE_CHARS[rng.gen_range(0..ROOM_CODE_CHARS.len())] as char)
+                .collect();
+            if !rooms.contains_key(&code) {
+                break code;
+            }
+        };
+
+        rooms.insert(
+            code.clone(),
+            Room {
+                players: vec![new_player(player_id, name, session)],
+                round: 1,
+                deadline: None,
+            },
+        );
+        code
+    }
+
+    /// 既存のルームに参加します．
+    fn join(&self, code: &str, player_id: u64, name: String, session: Session) -> anyhow::Result<()> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let room = rooms
+            .get_mut(code)
+            .ok_or_else(|| anyhow!("Error: Room not found"))?;
+        if room.players.len() >= MAX_ROOM_PLAYERS {
+            return Err(anyhow!("Error: Room is full (max {})", MAX_ROOM_PLAYERS));
+        }
+        room.players.push(new_player(player_id, name, session));
+        Ok(())
+    }
+
+    async fn joined(&self, room: &mut Option<String>, code: String, player_id: u64, session: &Session) {
+        let message = ServerMessage::Joined {
+            room: &code,
+            playerId: player_id,
+        };
+        let _ = session.clone().text(message.to_json()).await;
+        self.broadcast_state(&code).await;
+        *room = Some(code);
+    }
+
+    /// 提出されたデッキを確認し，重複を除いて返します．
+    /// デッキの制約を破っている場合と，異なるカードが5枚未満の場合はエラーになります．
+    pub(crate) fn check_deck(&self, mut deck: Vec<u32>) -> anyhow::Result<Vec<u32>> {
+        self.constraints.validate(&deck)?;
+        deck.sort_unstable();
+        deck.dedup();
+        if deck.len() < 5 {
+            return Err(anyhow!("Error: useCards must have at least 5 distinct cards"));
+        }
+        Ok(deck)
+    }
+
+    /// デッキを提出します．
+    fn submit_deck(&self, code: &str, player_id: u64, deck: Vec<u32>) -> anyhow::Result<Next> {
+        let deck = self.check_deck(deck)?;
+
+        let mut rooms = self.rooms.lock().unwrap();
+        let room = rooms
+            .get_mut(code)
+            .ok_or_else(|| anyhow!("Error: Room not found"))?;
+        if let Some(player) = room.players.iter_mut().find(|x| x.id == player_id) {
+            player.deck = Some(deck);
+        }
+
+        if room.players.len() >= 2 && room.players.iter().all(|x| x.deck.is_some()) {
+            Ok(Next::Play(room.round))
+        } else if room.deadline.is_none() {
+            room.deadline = Some(Instant::now() + Duration::from_secs(TURN_SECONDS));
+            Ok(Next::StartTimer(room.round))
+        } else {
+            Ok(Next::Wait)
+        }
+    }
+
+    /// roundの手札を配り，結果を保存してルームの全員に送ります．
+    /// そのroundがすでに終わっている場合は何もしません．
+    async fn play_round(&self, code: &str, round: u32) {
+        let (sessions, hands) = {
+            let mut rooms = self.rooms.lock().unwrap();
+            let room = match rooms.get_mut(code) {
+                Some(room) if room.round == round => room,
+                _ => return,
+            };
+            room.deadline = None;
+
+            let hands = room.deal(&self.sheet);
+            if hands.is_some() {
+                room.round += 1;
+                for player in room.players.iter_mut() {
+                    player.deck = None;
+                }
+            }
+            (room.sessions(), hands)
+        };
+
+        match hands {
+            Some(hands) => {
+                let match_id = match database::insert_match(&self.pool, code, round, &hands).await {
+                    Ok(id) => Some(id),
+                    Err(e) => {
+                        log::error!("failed to save match result: {}", e);
+                        None
+                    }
+                };
+                let message = ServerMessage::Result {
+                    room: code,
+                    round,
+                    matchId: match_id,
+                    hands: &hands,
+                };
+                send_all(sessions, message.to_json()).await;
+            }
+            None => {
+                let message = ServerMessage::Error {
+                    message: "Error: At least 2 players must submit decks".to_string(),
+                    violations: &[],
+                };
+                send_all(sessions, message.to_json()).await;
+            }
+        }
+        self.broadcast_state(code).await;
+    }
+
+    /// 参加しているルームから抜けます．最後の1人が抜けたルームは削除します．
+    async fn leave_room(&self, room: &mut Option<String>, player_id: u64) {
+        let code = match room.take() {
+            Some(code) => code,
+            None => return,
+        };
+
+        let empty = {
+            let mut rooms = self.rooms.lock().unwrap();
+            match rooms.get_mut(&code) {
+                Some(room) => {
+                    room.players.retain(|x| x.id != player_id);
+                    room.players.is_empty()
+                }
+                None => return,
+            }
+        };
+
+        if empty {
+            self.rooms.lock().unwrap().remove(&code);
+        } else {
+            self.broadcast_state(&code).await;
+        }
+    }
+
+    async fn broadcast_state(&self, code: &str) {
+        let state = self.rooms.lock().unwrap().get(code).map(|room| room.state(code));
+        if let Some((sessions, message)) = state {
+            send_all(sessions, message).await;
+        }
+    }
+}
+
+fn new_player(id: u64, name: String, session: Session) -> Player {
+    let name = name.trim();
+    Player {
+        id,
+        name: if name.is_empty() {
+            format!("Player{}", id)
+        } else {
+            name.chars().take(32).collect()
+        },
+        session,
+        deck: None,
+    }
+}
+
+/// 切断済みのセッションには送れないが，切断の処理は受信側で行うので無視する
+async fn send_all(sessions: Vec<Session>, message: String) {
+    for mut session in sessions {
+        let _ = session.text(message.clone()).await;
+    }
+}
+
+/// WebSocketの接続1つ分の処理です．切断されるまでメッセージを受け取って処理します．
+pub async fn run(hub: Arc<Hub>, mut session: Session, mut stream: MessageStream) {
+    let player_id = hub.next_player_id.fetch_add(1, Ordering::Relaxed);
+    let mut room = None;
+
+    while let Some(Ok(message)) = stream.recv().await {
+        match message {
+            Message::Text(text) => {
+                let result = match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(message) => hub.handle(player_id, &session, &mut room, message).await,
+                    Err(e) => Err(anyhow!("Error: Invalid message: {}", e)),
+                };
+                if let Err(e) = result {
+                    let _ = session.text(error_message(&e)).await;
+                }
+            }
+            Message::Ping(bytes) if session.pong(&bytes).await.is_err() => break,
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+
+    hub.leave_room(&mut room, player_id).await;
+    let _ = session.close(None).await;
+}
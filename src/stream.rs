@@ -0,0 +1,85 @@
+//! シミュレーションの途中経過をServer-Sent Eventsで送るモジュールです．
+//! シミュレーションは別スレッドで実行し，途中経過をチャンネル経由でレスポンスのストリームに流します．
+
+use std::convert::Infallible;
+use std::time::{Duration, Instant};
+
+use actix_web::web::Bytes;
+use futures_util::stream::{self, Stream};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+use crate::porker::{self, RoleCounts, ScoreSheet};
+use crate::{ErrorResponse, Response};
+
+/// 時間間隔で送る場合に，経過時間を確認する間隔(回数)です．
+const CHECK_EVERY: u32 = 1_000;
+
+/// 回数も時間も指定されなかった場合に使う送信間隔です．
+const DEFAULT_INTERVAL_MS: u64 = 200;
+
+/// /postcards/stream にPOSTされたデータを受け取るための構造体です．
+/// /postcardsのデータに加えて，途中経過を送る間隔を回数(every)か時間(intervalMs)で指定できます．
+/// 両方指定した場合は回数が優先されます．
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+pub struct StreamRequest {
+    num: u32,
+    #[serde(deserialize_with = "crate::deserialize_card_ids")]
+    useCards: Vec<u32>,
+    every: Option<u32>,
+    intervalMs: Option<u64>,
+}
+
+impl StreamRequest {
+    pub fn use_cards(&self) -> &[u32] {
+        &self.useCards
+    }
+}
+
+/// SSEのイベント1つ分の文字列を作ります．
+fn event(name: &str, data: &str) -> Bytes {
+    Bytes::from(format!("event: {}\ndata: {}\n\n", name, data))
+}
+
+fn response_event(name: &str, role_count: &RoleCounts, sum_score: i64, number: u32) -> Bytes {
+    let response = Response::new(sum_score, number, *role_count);
+    event(name, &serde_json::to_string(&response).unwrap_or_default())
+}
+
+/// シミュレーションを始め，途中経過(progress)と最終結果(result)のイベントを流すストリームを返します．
+/// エラーの場合は，ほかのAPIのエラーと同じ形式のJSONをerrorイベントで送って終わります．
+/// クライアントが切断した場合は，次の途中経過を送ろうとした時点でシミュレーションを中断します．
+pub fn start(request: StreamRequest, sheet: ScoreSheet) -> impl Stream<Item = Result<Bytes, Infallible>> {
+    let (tx, rx) = mpsc::channel::<Bytes>(16);
+
+    tokio::task::spawn_blocking(move || {
+        let loop_num = porker::limit_loop_num(request.num);
+        let interval = Duration::from_millis(request.intervalMs.unwrap_or(DEFAULT_INTERVAL_MS));
+        let mut last_sent = Instant::now();
+
+        let (progress_every, by_count) = match request.every {
+            Some(every) => (every, true),
+            None => (CHECK_EVERY, false),
+        };
+
+        let result = porker::run_porker(&request.useCards, loop_num, &sheet, progress_every, |role_count, sum_score, done| {
+            if !by_count && last_sent.elapsed() < interval {
+                return true;
+            }
+            last_sent = Instant::now();
+            tx.blocking_send(response_event("progress", role_count, sum_score, done)).is_ok()
+        });
+
+        let last = match result {
+            Ok((role_count, sum_score, loop_num)) => response_event("result", &role_count, sum_score, loop_num),
+            Err(e) => event("error", &serde_json::to_string(&ErrorResponse::from_error(&e)).unwrap_or_default()),
+        };
+        // 切断済みの場合は送れないが，送る相手もいないので無視する
+        let _ = tx.blocking_send(last);
+    });
+
+    stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|bytes| (Ok(bytes), rx))
+    })
+}
@@ -0,0 +1,276 @@
+use actix_web::http::StatusCode;
+use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+use actix_web::{web, App};
+use futures_util::StreamExt;
+use sqlx::SqlitePool;
+
+use crate::config::Config;
+use crate::database::{self, RunFilter};
+use crate::export;
+use crate::jobs::JobQueue;
+use crate::rooms::{self, Hub};
+use crate::stream;
+use crate::porker::batch::BatchFormat;
+use crate::porker::constraints::DeckConstraints;
+use crate::porker::{Limits, Role, RoleCounts, ScoreSheet};
+
+/// テストごとに別のファイルのデータベースを作ります．
+async fn test_pool(name: &str) -> SqlitePool {
+    let path = std::env::temp_dir().join(format!("porker_test_{}_{}.db", std::process::id(), name));
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+    let pool = database::create_sqlite_pool(&format!("sqlite:{}", path.display())).await.unwrap();
+    database::migrate_database(&pool).await.unwrap();
+    pool
+}
+
+/// スペード13枚とハートのAから7までの，20枚のデッキです．
+fn test_deck() -> Vec<u32> {
+    (0..20).collect()
+}
+
+/// 書き出した内容をすべてつなげて返します．
+async fn export_text(pool: &SqlitePool, format: BatchFormat) -> String {
+    let chunks: Vec<_> = export::start(pool.clone(), RunFilter::default(), format).collect().await;
+    chunks
+        .into_iter()
+        .map(|x| String::from_utf8(x.unwrap().to_vec()).unwrap())
+        .collect()
+}
+
+#[actix_web::test]
+async fn export_round_trips_stored_run() {
+    let pool = test_pool("export").await;
+    let mut role_count = RoleCounts::new();
+    role_count[Role::OnePair] = 3;
+    role_count[Role::Flush] = 1;
+    let id = database::insert_millionpoker(&pool, "postcards", &[0, 1, 12], 4, &role_count, 35)
+        .await
+        .unwrap();
+
+    let csv = export_text(&pool, BatchFormat::Csv).await;
+    let lines: Vec<&str> = csv.lines().collect();
+    let roles: Vec<&str> = Role::ALL.iter().map(|role| role.name()).collect();
+    assert_eq!(lines[0], format!("id,kind,createdAt,number,allscore,useCards,{}", roles.join(",")));
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].starts_with(&format!("{},postcards,", id)));
+    assert!(lines[1].ends_with(",4,35,\"0,1,12\",0,3,0,0,0,1,0,0,0,0"));
+
+    let jsonl = export_text(&pool, BatchFormat::Jsonl).await;
+    let rows: Vec<serde_json::Value> = jsonl.lines().map(|x| serde_json::from_str(x).unwrap()).collect();
+    let stored = database::list_runs(&pool, &RunFilter::default()).await.unwrap();
+    assert_eq!(rows, vec![serde_json::to_value(&stored[0]).unwrap()]);
+    assert_eq!(rows[0]["useCards"], serde_json::json!([0, 1, 12]));
+    assert_eq!(rows[0]["result"]["onepair"], 3);
+    assert_eq!(rows[0]["allscore"], 35);
+}
+
+/// 名前と値の組から，環境変数の代わりになる関数を作ります．
+fn fake_env(vars: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> {
+    move |name| vars.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_string())
+}
+
+#[test]
+fn config_defaults_from_toml() {
+    assert_eq!(toml::from_str::<Config>("").unwrap(), Config::default());
+    // 例の設定ファイルは初期値と同じ
+    assert_eq!(Config::from_file("porker.example.toml").unwrap(), Config::default());
+
+    let config: Config = toml::from_str(
+        "
+        port = 8080
+        corsOrigins = [\"*\"]
+
+        [limits]
+        maxLoopNum = 5000
+        ",
+    )
+    .unwrap();
+    assert_eq!(config.port, 8080);
+    assert_eq!(config.cors_origins, vec!["*".to_string()]);
+    assert_eq!(config.limits.max_loop_num, 5000);
+    // 書かなかった項目は初期値のまま
+    assert_eq!(config.host, "127.0.0.1");
+    assert_eq!(config.workers, None);
+    assert_eq!(config.limits.max_trace_loop_num, Limits::DEFAULT.max_trace_loop_num);
+}
+
+#[test]
+fn config_env_overrides_toml() {
+    let mut config: Config = toml::from_str(
+        "
+        host = \"0.0.0.0\"
+        port = 8080
+        logLevel = \"debug\"
+
+        [limits]
+        maxLoopNum = 5000
+        ",
+    )
+    .unwrap();
+    config
+        .apply_env(fake_env(&[
+            ("PORKER_PORT", "9000"),
+            ("PORKER_MAX_LOOP_NUM", " 123 "),
+            ("PORKER_JOB_WORKERS", "3"),
+            ("SCORE_SHEET", "sheet.json"),
+            ("PORKER_CORS_ORIGINS", "http://a.example, ,http://b.example"),
+        ]))
+        .unwrap();
+
+    assert_eq!(config.port, 9000);
+    assert_eq!(config.limits.max_loop_num, 123);
+    assert_eq!(config.job_workers, Some(3));
+    assert_eq!(config.score_sheet.as_deref(), Some("sheet.json"));
+    assert_eq!(config.cors_origins, vec!["http://a.example", "http://b.example"]);
+    // 環境変数が無い項目はTOMLの値のまま
+    assert_eq!(config.host, "0.0.0.0");
+    assert_eq!(config.log_level, "debug");
+    assert_eq!(config.workers, None);
+
+    // 環境変数が1つも無ければ何も変わらない
+    let before = config.clone();
+    config.apply_env(|_| None).unwrap();
+    assert_eq!(config, before);
+}
+
+#[test]
+fn config_rejects_malformed_env() {
+    for vars in [
+        &[("PORKER_PORT", "http")][..],
+        &[("PORKER_MAX_LOOP_NUM", "-1")][..],
+        &[("PORKER_WORKERS", "many")][..],
+    ] {
+        let error = Config::default().apply_env(fake_env(vars)).unwrap_err();
+        assert!(error.to_string().contains(vars[0].0), "{}", error);
+    }
+}
+
+#[actix_web::test]
+async fn bad_requests_use_error_envelope() {
+    let constraints = DeckConstraints {
+        banned_cards: vec![crate::porker::Card::new(0)],
+        ..Default::default()
+    };
+    let app = init_service(
+        App::new()
+            .configure(|cfg| crate::extractor_configs(cfg, 256))
+            .app_data(web::Data::new(constraints))
+            .app_data(web::Data::new(ScoreSheet::default()))
+            .app_data(web::Data::new(JobQueue::new(1, 1000, 1)))
+            .service(crate::analyze_cards)
+            .service(crate::compare_decks)
+            .service(crate::create_job)
+            .service(crate::get_job),
+    )
+    .await;
+
+    let deck: Vec<u32> = (1..21).collect();
+    let cases = [
+        // JSONとして読めない
+        (
+            TestRequest::post()
+                .uri("/analyze")
+                .insert_header(("Content-Type", "application/json"))
+                .set_payload("{"),
+            StatusCode::BAD_REQUEST,
+        ),
+        // 本文が上限より大きい
+        (
+            TestRequest::post()
+                .uri("/analyze")
+                .set_json(serde_json::json!({ "useCards": vec![1; 200], "num": 10 })),
+            StatusCode::PAYLOAD_TOO_LARGE,
+        ),
+        // シミュレーションのエラー
+        (
+            TestRequest::post()
+                .uri("/compare")
+                .set_json(serde_json::json!({ "decks": [deck], "num": 10 })),
+            StatusCode::BAD_REQUEST,
+        ),
+        // ジョブでは使えない実行方法
+        (
+            TestRequest::post()
+                .uri("/jobs")
+                .set_json(serde_json::json!({ "useCards": deck, "num": 10, "trace": true })),
+            StatusCode::BAD_REQUEST,
+        ),
+        // パスを読み込めない
+        (TestRequest::get().uri("/jobs/abc"), StatusCode::BAD_REQUEST),
+        (TestRequest::get().uri("/jobs/1"), StatusCode::NOT_FOUND),
+    ];
+    for (request, status) in cases {
+        let response = call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), status);
+        let body: serde_json::Value = read_body_json(response).await;
+        assert!(body["error"].as_str().unwrap().starts_with("Error: "), "{}", body);
+        assert!(!body["error"].as_str().unwrap().starts_with("Error: Error: "), "{}", body);
+        assert_eq!(body["violations"], serde_json::json!([]));
+    }
+
+    // 制約を破っている場合は，破っているルールが入る
+    let request = TestRequest::post()
+        .uri("/analyze")
+        .set_json(serde_json::json!({ "useCards": test_deck(), "num": 10 }));
+    let response = call_service(&app, request.to_request()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = read_body_json(response).await;
+    assert_eq!(body["violations"][0]["rule"], "bannedCards");
+}
+
+#[actix_web::test]
+async fn job_queue_rejects_when_full() {
+    let queue = JobQueue::new(1, 100_000_000, 1);
+    let deck = test_deck();
+
+    let first = queue.submit(deck.clone(), 100_000_000, ScoreSheet::default());
+    assert!(first.is_some());
+    assert!(queue.submit(deck.clone(), 10, ScoreSheet::default()).is_none());
+
+    // キャンセルして終わればまた受け付ける
+    queue.cancel(1).unwrap();
+    for _ in 0..100 {
+        if queue.submit(deck.clone(), 10, ScoreSheet::default()).is_some() {
+            return;
+        }
+        actix_web::rt::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    panic!("cancelled job still counts as pending");
+}
+
+#[actix_web::test]
+async fn stream_error_uses_error_envelope() {
+    let request = serde_json::from_value(serde_json::json!({ "useCards": [1, 1, 1, 1, 1], "num": 10 })).unwrap();
+    let chunks: Vec<_> = stream::start(request, ScoreSheet::default()).collect().await;
+    let last = String::from_utf8(chunks.last().unwrap().as_ref().unwrap().to_vec()).unwrap();
+
+    let data = last.strip_prefix("event: error\ndata: ").unwrap();
+    let body: serde_json::Value = serde_json::from_str(data.trim_end()).unwrap();
+    assert!(body["error"].as_str().unwrap().starts_with("Error: "), "{}", body);
+    assert_eq!(body["violations"], serde_json::json!([]));
+}
+
+#[actix_web::test]
+async fn room_decks_follow_deck_constraints() {
+    let constraints = DeckConstraints {
+        banned_cards: vec![crate::porker::Card::new(0)],
+        ..Default::default()
+    };
+    let hub = Hub::new(test_pool("rooms").await, ScoreSheet::default(), constraints);
+
+    assert_eq!(hub.check_deck(vec![5, 1, 2, 3, 4, 1]).unwrap(), vec![1, 2, 3, 4, 5]);
+
+    let error = hub.check_deck(test_deck()).unwrap_err();
+    let message: serde_json::Value = serde_json::from_str(&rooms::error_message(&error)).unwrap();
+    assert_eq!(message["type"], "error");
+    assert_eq!(message["message"], "Error: useCards breaks deck constraints");
+    assert_eq!(message["violations"][0]["rule"], "bannedCards");
+
+    // 制約以外のエラーでは空になる
+    let error = hub.check_deck(vec![1, 1, 2, 3, 4]).unwrap_err();
+    let message: serde_json::Value = serde_json::from_str(&rooms::error_message(&error)).unwrap();
+    assert!(message["message"].as_str().unwrap().starts_with("Error: "), "{}", message);
+    assert_eq!(message["violations"], serde_json::json!([]));
+}